@@ -0,0 +1,352 @@
+// Checks that src/main.rs would survive being pasted into the CodinGame IDE:
+// one file, std only, and a bundle that fits the IDE's size limit, builds
+// with plain rustc, and answers every turn of a recorded game in time. The
+// bundle, comments and tests stripped, is written to target/submission.rs
+// for pasting.
+//
+// cargo run --bin check_submission [multi-turn fixture]
+
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Write},
+    iter::Peekable,
+    path::Path,
+    process::{self, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+const SUBMISSION: &str = "src/main.rs";
+const BUNDLE: &str = "target/submission.rs";
+const MANIFEST: &str = "Cargo.toml";
+const DEFAULT_FIXTURE: &str = "tests/recorded_game.txt";
+// the IDE refuses source over 100k characters
+const MAX_SUBMISSION_BYTES: usize = 100_000;
+// the judge allows 1s for the first turn, 50ms afterwards
+const COLD_START_BUDGET: Duration = Duration::from_millis(1000);
+const TURN_BUDGET: Duration = Duration::from_millis(50);
+// the test module is compiled out of the submission, file and all
+const TEST_MODULE: &str = "#[cfg(test)]\nmod tests;\n";
+// so is every item behind a cargo feature: the judge builds with none
+const FEATURE_GATE: &str = "#[cfg(feature = ";
+
+fn fail(message: &str) -> ! {
+    eprintln!("submission check FAILED: {}", message);
+    process::exit(1);
+}
+
+fn check_no_dependencies() {
+    let manifest = fs::read_to_string(MANIFEST).unwrap_or_else(|e| fail(&e.to_string()));
+    let mut in_dependencies = false;
+    for line in manifest.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_dependencies = line.ends_with("dependencies]");
+        } else if in_dependencies && !line.is_empty() && !line.starts_with('#') {
+            fail(&format!("external crate in {}: {}", MANIFEST, line));
+        }
+    }
+}
+
+fn check_single_file(source: &str) {
+    let mut previous = "";
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        let is_file_module = line.starts_with("mod ") && line.ends_with(';');
+        let is_test_module = is_file_module && previous == "#[cfg(test)]";
+        previous = line;
+        if (is_file_module && !is_test_module) || line.starts_with("extern crate") {
+            fail(&format!(
+                "{}:{} pulls in code from elsewhere: {}",
+                SUBMISSION,
+                number + 1,
+                line
+            ));
+        }
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn is_operator(c: char) -> bool {
+    "+-*/%&|^!<>=.:@#$?~".contains(c)
+}
+
+// copies a string literal, `chars` just past its opening quote
+fn copy_string(chars: &mut impl Iterator<Item = char>, out: &mut String) {
+    while let Some(c) = chars.next() {
+        out.push(c);
+        match c {
+            '\\' => out.extend(chars.next()),
+            '"' => return,
+            _ => {}
+        }
+    }
+}
+
+// copies a raw string's hashes, body and closing quote and hashes
+fn copy_raw_string(chars: &mut Peekable<impl Iterator<Item = char>>, out: &mut String) {
+    let mut hashes = 0;
+    while let Some('#') = chars.peek() {
+        out.push(chars.next().unwrap());
+        hashes += 1;
+    }
+    out.extend(chars.next());
+    let mut closing = None;
+    for c in chars.by_ref() {
+        out.push(c);
+        closing = match (c, closing) {
+            ('"', _) => Some(0),
+            ('#', Some(count)) => Some(count + 1),
+            _ => None,
+        };
+        if closing == Some(hashes) {
+            return;
+        }
+    }
+}
+
+// brackets of any kind a line opens minus those it closes, outside strings
+fn bracket_balance(line: &str) -> i32 {
+    let mut balance = 0;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' | '{' => balance += 1,
+            ')' | ']' | '}' => balance -= 1,
+            '"' => copy_string(&mut chars, &mut String::new()),
+            _ => {}
+        }
+    }
+    balance
+}
+
+// `source` without the items under a FEATURE_GATE attribute, going by
+// rustfmt's layout: an item ends on the first line that balances its
+// brackets and ends in `,`, `;` or `}`
+fn strip_feature_items(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut lines = source.lines();
+    while let Some(line) = lines.next() {
+        if !line.trim().starts_with(FEATURE_GATE) {
+            out += line;
+            out.push('\n');
+            continue;
+        }
+        let mut depth = 0;
+        for line in lines.by_ref() {
+            depth += bracket_balance(line);
+            if depth == 0 && line.trim_end().ends_with(&[',', ';', '}'][..]) {
+                break;
+            }
+        }
+    }
+    out
+}
+
+// The submission as pasted: no comments, no test module and no feature
+// gated items, and whitespace only between two words or two operator
+// characters, where dropping it would join them into one token.
+fn bundle(source: &str) -> String {
+    let source = strip_feature_items(&source.replacen(TEST_MODULE, "", 1));
+    let mut out = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    let mut space = false;
+    // whether the last token was a literal; a word right after one would be
+    // read as its suffix
+    let mut after_literal = false;
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+        if c == '/' && chars.peek() == Some(&'/') {
+            chars.by_ref().find(|&c| c == '\n');
+            space = true;
+            continue;
+        }
+        if c == '/' && chars.peek() == Some(&'*') {
+            chars.next();
+            let mut last = ' ';
+            for c in chars.by_ref() {
+                if last == '*' && c == '/' {
+                    break;
+                }
+                last = c;
+            }
+            space = true;
+            continue;
+        }
+
+        if let Some(last) = out.chars().last() {
+            let joins = (is_word(last) || after_literal) && is_word(c)
+                || is_operator(last) && is_operator(c);
+            if space && joins {
+                out.push(' ');
+            }
+        }
+        space = false;
+        after_literal = false;
+        out.push(c);
+        match c {
+            '"' => {
+                copy_string(&mut chars, &mut out);
+                after_literal = true;
+            }
+            'r' if matches!(chars.peek(), Some('"') | Some('#'))
+                && !out[..out.len() - 1].ends_with(is_word) =>
+            {
+                copy_raw_string(&mut chars, &mut out);
+                after_literal = true;
+            }
+            '\'' => {
+                // a char literal, or else a lifetime, which is copied as a word
+                let mut ahead = chars.clone();
+                let literal = match ahead.next() {
+                    Some('\\') => true,
+                    Some(_) => ahead.next() == Some('\''),
+                    None => false,
+                };
+                if literal {
+                    while let Some(c) = chars.next() {
+                        out.push(c);
+                        match c {
+                            '\\' => out.extend(chars.next()),
+                            '\'' => break,
+                            _ => {}
+                        }
+                    }
+                    after_literal = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    out.push('\n');
+    out
+}
+
+fn compile(bundle: &Path, output: &Path) {
+    let status = Command::new("rustc")
+        .args(["--edition", "2018", "-C", "opt-level=3", "-o"])
+        .arg(output)
+        .arg(bundle)
+        .status()
+        .unwrap_or_else(|e| fail(&format!("could not run rustc: {}", e)));
+    if !status.success() {
+        fail("rustc rejected the single-file build");
+    }
+}
+
+// one turn per element: the entity count line, the entities, both inventories
+fn split_turns(stream: &str) -> Vec<String> {
+    let mut lines = stream.lines().filter(|line| !line.trim().is_empty());
+    let mut turns = Vec::new();
+    while let Some(count) = lines.next() {
+        let count: usize = count
+            .trim()
+            .parse()
+            .unwrap_or_else(|_| fail(&format!("not an entity count: {}", count)));
+        let mut turn = format!("{}\n", count);
+        for _ in 0..count + 2 {
+            let line = lines
+                .next()
+                .unwrap_or_else(|| fail("fixture ends mid-turn"));
+            turn += line;
+            turn.push('\n');
+        }
+        turns.push(turn);
+    }
+    turns
+}
+
+// Plays the fixture through the judge protocol, writing each turn only after
+// the previous answer, and returns how long each answer took.
+fn measure_turns(binary: &Path, fixture: &str) -> Vec<Duration> {
+    let stream =
+        fs::read_to_string(fixture).unwrap_or_else(|e| fail(&format!("{}: {}", fixture, e)));
+    let turns = split_turns(&stream);
+    if turns.len() < 2 {
+        fail(&format!("{} needs at least two turns", fixture));
+    }
+
+    let mut child = Command::new(binary)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap_or_else(|e| fail(&format!("could not start submission: {}", e)));
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+
+    let mut times = Vec::new();
+    let mut start = Instant::now();
+    for (number, turn) in turns.iter().enumerate() {
+        if number > 0 {
+            start = Instant::now();
+        }
+        stdin.write_all(turn.as_bytes()).unwrap();
+        stdin.flush().unwrap();
+        let mut command = String::new();
+        stdout.read_line(&mut command).unwrap();
+        times.push(start.elapsed());
+        if command.trim().is_empty() {
+            fail(&format!("submission printed nothing for turn {}", number));
+        }
+        if number == 0 {
+            println!("first command: {}", command.trim());
+        }
+    }
+    drop(stdin);
+    let _ = child.wait();
+    times
+}
+
+fn main() {
+    let fixture = env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_FIXTURE.to_string());
+
+    check_no_dependencies();
+    let source = fs::read_to_string(SUBMISSION).unwrap_or_else(|e| fail(&e.to_string()));
+    check_single_file(&source);
+    let bundled = bundle(&source);
+    fs::write(BUNDLE, &bundled).unwrap_or_else(|e| fail(&format!("{}: {}", BUNDLE, e)));
+    println!(
+        "{}: {} bytes, single file, std only; {}: {} bytes (limit {})",
+        SUBMISSION,
+        source.len(),
+        BUNDLE,
+        bundled.len(),
+        MAX_SUBMISSION_BYTES
+    );
+    if bundled.len() > MAX_SUBMISSION_BYTES {
+        fail("the bundle is over the IDE's size limit");
+    }
+
+    let binary = env::temp_dir().join("codingame_submission");
+    let compile_start = Instant::now();
+    compile(Path::new(BUNDLE), &binary);
+    println!("rustc build: {:?}", compile_start.elapsed());
+
+    let times = measure_turns(&binary, &fixture);
+    println!(
+        "cold start: {:?} (budget {:?})",
+        times[0], COLD_START_BUDGET
+    );
+    if times[0] > COLD_START_BUDGET {
+        fail("first turn is over the judge's time limit");
+    }
+    let slowest = times[1..].iter().max().unwrap();
+    println!(
+        "slowest of {} later turns: {:?} (budget {:?})",
+        times.len() - 1,
+        slowest,
+        TURN_BUDGET
+    );
+    if *slowest > TURN_BUDGET {
+        fail("a later turn is over the judge's time limit");
+    }
+
+    println!("submission check passed");
+}
@@ -1,4 +1,12 @@
-use std::{collections::{HashMap, HashSet, LinkedList, VecDeque}, io::{self, BufRead}, time::{Duration, Instant}};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BinaryHeap, HashMap, HashSet, LinkedList},
+    fmt, fs,
+    hash::{Hash, Hasher},
+    io::{self, BufRead, Write},
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
 
 macro_rules! parse_input {
     ($x:expr, $t:ident) => {
@@ -34,16 +42,16 @@ mod vec4 {
             }
         }
 
-        pub fn magnitude2(self) -> f64 {
-            (self.x * self.x + self.y * self.y + self.z * self.z + self.w * self.w) as f64
+        pub fn is_non_neg(self) -> bool {
+            self.x >= 0 && self.y >= 0 && self.z >= 0 && self.w >= 0
         }
 
-        pub fn magnitude(self) -> f64 {
-            f64::sqrt(self.magnitude2())
+        pub fn total(self) -> i32 {
+            self.x + self.y + self.z + self.w
         }
 
-        pub fn is_non_neg(self) -> bool {
-            self.x >= 0 && self.y >= 0 && self.z >= 0 && self.w >= 0
+        pub fn to_array(self) -> [i32; 4] {
+            [self.x, self.y, self.z, self.w]
         }
     }
 
@@ -100,37 +108,121 @@ mod vec4 {
 
 use vec4::Vec4;
 
+// An id as the judge assigns it to spells, tome spells and orders. Keeping
+// it apart from plain integers and vector positions stops an id being used
+// as an index by accident.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash)]
+struct EngineId(i32);
+
+// added to a tome spell's id to name the spell it becomes once learned
+const LEARNED_ID_OFFSET: i32 = 1000;
+
+impl EngineId {
+    // synthetic id for a spell learned during search, before the judge names it
+    fn learned(self) -> Self {
+        EngineId(self.0 + LEARNED_ID_OFFSET)
+    }
+}
+
+impl fmt::Display for EngineId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// Positions in one state's entity vectors, from State::spell_slot and
+// friends. Brewing or learning shifts later entries, so a slot must not
+// outlive the state it was looked up in.
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+struct SpellSlot(usize);
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+struct OrderSlot(usize);
+
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
+struct TomeSlot(usize);
+
+#[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
 enum Action {
-    Learn(i32),
-    Brew(i32),
-    Cast(i32),
+    Learn(EngineId),
+    Brew(EngineId),
+    Cast(EngineId),
     Rest,
     Wait,
 }
 
-impl ToString for Action {
-    fn to_string(&self) -> String {
+impl Action {
+    // the command line the judge expects for this action
+    fn to_command(self) -> String {
+        match self {
+            Action::Learn(id) => format!("LEARN {}", id),
+            Action::Cast(id) => format!("CAST {}", id),
+            Action::Brew(id) => format!("BREW {}", id),
+            Action::Rest => "REST".to_string(),
+            Action::Wait => "WAIT".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Action::Learn(id) => format!("Learn {}", id),
-            Action::Cast(id) => format!("Cast {}", id),
-            Action::Brew(id) => format!("Brew {}", id),
-            Action::Rest => "Rest".to_string(),
-            Action::Wait => "Wait".to_string(),
+            Action::Learn(id) => write!(f, "Learn {}", id),
+            Action::Cast(id) => write!(f, "Cast {}", id),
+            Action::Brew(id) => write!(f, "Brew {}", id),
+            Action::Rest => write!(f, "Rest"),
+            Action::Wait => write!(f, "Wait"),
+        }
+    }
+}
+
+// The checked way to play an action: the only way to get a Validated is
+// State::validate, so State::apply can't be handed an unchecked action.
+mod checked {
+    use super::{Action, IllegalAction, State};
+
+    // an action checked against the state it is about to be applied to
+    #[derive(Debug, Copy, Clone)]
+    pub struct Validated(Action);
+
+    impl Validated {
+        pub fn action(self) -> Action {
+            self.0
+        }
+    }
+
+    impl State {
+        pub fn validate(&self, action: Action) -> Result<Validated, IllegalAction> {
+            if self.is_legal(action) {
+                Ok(Validated(action))
+            } else {
+                Err(IllegalAction(action))
+            }
         }
     }
 }
 
+use checked::Validated;
+
+#[derive(Debug, Copy, Clone)]
+struct IllegalAction(Action);
+
+impl fmt::Display for IllegalAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "illegal action {}", self.0)
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
 struct Spell {
-    pub id: i32,
+    pub id: EngineId,
     pub is_repeatable: bool,
     pub is_castable: bool,
     pub delta: Vec4,
 }
 
 impl Spell {
-    fn new(id: i32, is_repeatable: bool, is_castable: bool, delta: Vec4) -> Self {
+    fn new(id: EngineId, is_repeatable: bool, is_castable: bool, delta: Vec4) -> Self {
         Self {
             id,
             is_repeatable,
@@ -140,13 +232,18 @@ impl Spell {
     }
 
     fn can_be_afforded_by(self, inventory: Vec4) -> bool {
-        (inventory + self.delta).is_non_neg()
+        let result = inventory + self.delta;
+        result.is_non_neg() && result.total() <= MAX_INVENTORY
+    }
+
+    fn is_downgrade(self) -> bool {
+        is_downgrade(self.delta)
     }
 }
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
 struct TomeSpell {
-    pub id: i32,
+    pub id: EngineId,
     pub is_repeatable: bool,
     pub tome_index: i32,
     pub tax_count: i32,
@@ -154,7 +251,13 @@ struct TomeSpell {
 }
 
 impl TomeSpell {
-    fn new(id: i32, is_repeatable: bool, tome_index: i32, tax_count: i32, delta: Vec4) -> Self {
+    fn new(
+        id: EngineId,
+        is_repeatable: bool,
+        tome_index: i32,
+        tax_count: i32,
+        delta: Vec4,
+    ) -> Self {
         Self {
             id,
             is_repeatable,
@@ -163,23 +266,126 @@ impl TomeSpell {
             delta,
         }
     }
+
+    fn can_be_learned_by(self, inventory: Vec4) -> bool {
+        inventory.x >= self.tome_index
+    }
+
+    fn is_downgrade(self) -> bool {
+        is_downgrade(self.delta)
+    }
+
+    // tier-0 ingredients paid minus those collected when learning, in rupee terms
+    fn net_learn_cost(self) -> f64 {
+        (self.tome_index - self.tax_count) as f64 * TIER_VALUES[0]
+    }
+
+    fn cast_value(self) -> f64 {
+        inventory_value(self.delta)
+    }
+
+    // turns until the casts have paid back the learn: the learn turn itself,
+    // then one cast per cast+rest cycle of two turns
+    fn payback_turns(self) -> Option<u32> {
+        let cast_value = self.cast_value();
+        if cast_value <= 0.0 {
+            return None;
+        }
+        let casts = (self.net_learn_cost().max(0.0) / cast_value).ceil() as u32;
+        Some(1 + casts * 2)
+    }
 }
 
-#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+// a spell that turns some higher-tier ingredient back into lower tiers
+fn is_downgrade(delta: Vec4) -> bool {
+    let tiers = delta.to_array();
+    (0..4).any(|low| tiers[low] > 0 && tiers[low + 1..].iter().any(|&high| high < 0))
+}
+
+#[derive(Debug, Copy, Clone)]
 struct Order {
-    id: i32,
+    id: EngineId,
     price: i32,
     delta: Vec4,
+    // turns the order has been on the board since we first saw it
+    age: u32,
+    // ingredients required, packed like pack_guarded but without the guards
+    need: u32,
+    // percent chance we brew it before the opponent, see Bot::estimate_races
+    claim: u8,
+    // urgency bonus included in `price`, 0 once the bonuses have run out
+    bonus: i32,
+}
+
+// Equal by key alone, so a state searched last turn still equals the one
+// the judge sends this turn: see Kept.
+impl PartialEq for Order {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Order {}
+
+impl Hash for Order {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.key().hash(hasher);
+    }
+}
+
+// One tier per 8-bit lane. With every lane's top bit set as a borrow guard,
+// subtracting an order's `need` clears a lane's guard exactly when that tier
+// is short, so one subtraction checks all four tiers at once.
+const LANE_GUARDS: u32 = 0x8080_8080;
+
+fn pack_lanes(v: Vec4) -> u32 {
+    let lane = |count: i32| count.clamp(0, 0x7f) as u32;
+    lane(v.x) | lane(v.y) << 8 | lane(v.z) << 16 | lane(v.w) << 24
 }
 
+fn pack_guarded(inventory: Vec4) -> u32 {
+    pack_lanes(inventory) | LANE_GUARDS
+}
+
+// orders left on the board this long are probably contested or not worth it
+const STALE_ORDER_AGE: u32 = 15;
+// rupees knocked off a stale order's worth per turn past STALE_ORDER_AGE
+const STALE_ORDER_PENALTY: f64 = 1.0;
+
 impl Order {
-    fn new(id: i32, price: i32, delta: Vec4) -> Self {
-        Self { id, price, delta }
+    fn new(id: EngineId, price: i32, delta: Vec4) -> Self {
+        let need = Vec4::new(-delta.x, -delta.y, -delta.z, -delta.w);
+        Self {
+            id,
+            price,
+            delta,
+            age: 0,
+            need: pack_lanes(need),
+            claim: 100,
+            bonus: 0,
+        }
     }
 
     fn can_be_fulfilled_by(self, inventory: Vec4) -> bool {
         (inventory + self.delta).is_non_neg()
     }
+
+    // what the judge sends; age and claim are our own annotations
+    fn key(self) -> (EngineId, i32, Vec4, i32) {
+        (self.id, self.price, self.delta, self.bonus)
+    }
+
+    fn is_stale(self) -> bool {
+        self.age > STALE_ORDER_AGE
+    }
+
+    // what the order is worth as a target: its price, less a penalty for
+    // age, scaled by the chance the opponent doesn't take it first
+    fn target_value(self) -> f64 {
+        let overdue = self.age.saturating_sub(STALE_ORDER_AGE);
+        let value = self.price as f64 - overdue as f64 * STALE_ORDER_PENALTY;
+        value * self.claim as f64 / 100.0
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Hash)]
@@ -192,18 +398,8 @@ impl MagicTome {
         Self { spells }
     }
 
-    fn add_spell(&mut self, spell: TomeSpell) {
-        self.spells.push(spell)
-    }
-
-    fn remove_spell(&mut self, spell: TomeSpell) {
-        if let Some(index) = self.spells.iter().position(|s| s.id == spell.id) {
-            self.spells.remove(index);
-        }
-    }
-
-    fn remove_spell_at(&mut self, index: usize) {
-        self.spells.remove(index);
+    fn remove_spell_at(&mut self, slot: TomeSlot) {
+        self.spells.remove(slot.0);
     }
 }
 
@@ -212,28 +408,129 @@ struct Player {
     score: i32,
     inventory: Vec4,
     spells: Vec<Spell>,
+    // synergy::best_cycle_value of `spells` in hundredths, kept so the
+    // evaluator doesn't redo the cycle search on every node
+    cycle_value: i32,
+    // potions brewed this game, counting those planned along a search path;
+    // Session::observe sets it, the judge doesn't send it
+    brew_count: u32,
 }
 
 impl Player {
     fn new(score: i32, inventory: Vec4, spells: Vec<Spell>) -> Self {
-        Self {
+        let mut player = Self {
             score,
             inventory,
             spells,
-        }
+            cycle_value: 0,
+            brew_count: 0,
+        };
+        player.update_cycle_value();
+        player
+    }
+
+    fn update_cycle_value(&mut self) {
+        let deltas: Vec<Vec4> = self.spells.iter().map(|s| s.delta).collect();
+        self.cycle_value = (synergy::best_cycle_value(&deltas) * 100.0).round() as i32;
     }
 }
 
+const MAX_INVENTORY: i32 = 10;
+
+// rupee-equivalent worth of one ingredient of each tier
+const TIER_VALUES: [f64; 4] = [1.0, 2.0, 3.0, 4.0];
+const SCORE_WEIGHT: f64 = 10.0;
+// fraction of an average cast a castable spell is worth; resting re-enables
+// every exhausted spell, so this is also what the evaluator pays for a Rest
+const CASTABLE_WEIGHT: f64 = 0.5;
+// per free slot per castable producer: keeps a full inventory from looking
+// strictly better than one with room left to cast into
+const SLACK_WEIGHT: f64 = 0.1;
+// per rupee a turn my best rest cycle earns; only learning changes it, so it
+// is what the evaluator credits a learn with beyond its ingredients
+const CYCLE_WEIGHT: f64 = 6.0;
+// turns State::find_liquidation_action looks ahead
+const LIQUIDATION_DEPTH: usize = 4;
+
+fn inventory_value(inventory: Vec4) -> f64 {
+    inventory.x as f64 * TIER_VALUES[0]
+        + inventory.y as f64 * TIER_VALUES[1]
+        + inventory.z as f64 * TIER_VALUES[2]
+        + inventory.w as f64 * TIER_VALUES[3]
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Hash)]
 struct State {
     me: Player,
     orders: Vec<Order>,
     tome: MagicTome,
+    enemy_score: i32,
+    // the opponent's Player::cycle_value, from its visible spells
+    enemy_cycle_value: i32,
 }
 //
 impl State {
     fn new(me: Player, tome: MagicTome, orders: Vec<Order>) -> Self {
-        Self { me, tome, orders }
+        Self {
+            me,
+            tome,
+            orders,
+            enemy_score: 0,
+            enemy_cycle_value: 0,
+        }
+    }
+
+    fn spell_slot(&self, id: EngineId) -> Option<SpellSlot> {
+        self.me
+            .spells
+            .iter()
+            .position(|s| s.id == id)
+            .map(SpellSlot)
+    }
+
+    fn order_slot(&self, id: EngineId) -> Option<OrderSlot> {
+        self.orders.iter().position(|o| o.id == id).map(OrderSlot)
+    }
+
+    fn tome_slot(&self, id: EngineId) -> Option<TomeSlot> {
+        self.tome
+            .spells
+            .iter()
+            .position(|s| s.id == id)
+            .map(TomeSlot)
+    }
+
+    // the opponent brews the dearest order its inventory covers, paying for
+    // it out of that inventory, so the same ingredients can't snipe twice
+    fn lose_order_to(&mut self, rival: &mut Vec4) {
+        let sniped = self
+            .orders
+            .iter()
+            .enumerate()
+            .filter(|(_, o)| o.can_be_fulfilled_by(*rival))
+            .max_by_key(|(_, o)| o.price)
+            .map(|(index, _)| index);
+        if let Some(index) = sniped {
+            *rival += self.orders.remove(index).delta;
+        }
+    }
+
+    // size of one copy, heap included
+    fn approx_bytes(&self) -> usize {
+        std::mem::size_of::<State>()
+            + self.me.spells.len() * std::mem::size_of::<Spell>()
+            + self.orders.len() * std::mem::size_of::<Order>()
+            + self.tome.spells.len() * std::mem::size_of::<TomeSpell>()
+    }
+
+    // rupees ahead of the opponent; negative when behind
+    fn score_margin(&self) -> i32 {
+        self.me.score - self.enemy_score
+    }
+
+    // how much more a rest cycle earns the opponent than me, in rupees per turn
+    fn engine_gap(&self) -> f64 {
+        (self.enemy_cycle_value - self.me.cycle_value) as f64 / 100.0
     }
 
     fn get_possible_actions(&self) -> Vec<Action> {
@@ -246,21 +543,23 @@ impl State {
         //     }
         // }
 
-        for order in self.orders.iter() {
-            if order.can_be_fulfilled_by(self.me.inventory) {
-                actions.push(Action::Brew(order.id));
-            }
-        }
+        actions.extend(self.brews_by_price());
 
+        // most valuable cast first, so depth-limited and pruned searches
+        // reach the promising branches early; sort_by is stable, ties keep
+        // spell order
+        let mut casts = Vec::new();
         for spell in self.me.spells.iter() {
             if spell.is_castable {
                 if spell.can_be_afforded_by(self.me.inventory) {
-                    actions.push(Action::Cast(spell.id));
+                    casts.push((inventory_value(spell.delta), Action::Cast(spell.id)));
                 }
             } else {
                 can_use_rest = true;
             }
         }
+        casts.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        actions.extend(casts.into_iter().map(|(_, action)| action));
 
         if can_use_rest {
             actions.push(Action::Rest);
@@ -269,42 +568,114 @@ impl State {
         actions
     }
 
-    fn apply(&mut self, action: Action) {
+    // same as get_possible_actions, but the Cast/Rest part is shared between all
+    // nodes of a search with the same inventory and castable spells
+    fn get_possible_actions_cached(&self, cache: &mut CastCache) -> Vec<Action> {
+        let key = (
+            self.me.inventory,
+            self.castable_mask(),
+            self.me.spells.len(),
+        );
+        let spell_actions = cache.entry(key).or_insert_with(|| {
+            self.get_possible_actions()
+                .into_iter()
+                .filter(|a| !matches!(a, Action::Brew(_)))
+                .collect()
+        });
+
+        let mut actions = self.brews_by_price();
+        actions.extend_from_slice(spell_actions);
+        actions
+    }
+
+    // dearest brewable order first
+    fn brews_by_price(&self) -> Vec<Action> {
+        let mut orders: Vec<&Order> = self.brewable_orders().collect();
+        orders.sort_by_key(|o| std::cmp::Reverse(o.price));
+        orders.into_iter().map(|o| Action::Brew(o.id)).collect()
+    }
+
+    // bit i is set when my i-th spell is castable
+    fn castable_mask(&self) -> u64 {
+        self.me
+            .spells
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.is_castable)
+            .fold(0, |mask, (i, _)| mask | 1 << i)
+    }
+
+    fn is_legal(&self, action: Action) -> bool {
+        let inventory = self.me.inventory;
+        match action {
+            Action::Brew(id) => self
+                .orders
+                .iter()
+                .any(|o| o.id == id && o.can_be_fulfilled_by(inventory)),
+            Action::Cast(id) => self
+                .me
+                .spells
+                .iter()
+                .any(|s| s.id == id && s.is_castable && s.can_be_afforded_by(inventory)),
+            Action::Learn(id) => self
+                .tome
+                .spells
+                .iter()
+                .any(|s| s.id == id && s.can_be_learned_by(inventory)),
+            // the judge takes any REST, but one with nothing to refresh
+            // is a wasted turn, and get_possible_actions never offers it
+            Action::Rest => self.me.spells.iter().any(|s| !s.is_castable),
+            Action::Wait => true,
+        }
+    }
+
+    // checked entry point for anything that doesn't generate its own actions
+    fn try_apply(&mut self, action: Action) -> Result<(), IllegalAction> {
+        let action = self.validate(action)?;
+        self.apply(action);
+        Ok(())
+    }
+
+    fn apply(&mut self, action: Validated) {
+        self.apply_unchecked(action.action())
+    }
+
+    // search hot path: `action` must be one a generator (get_possible_actions,
+    // a brewable order, a search's expansion) just produced for this very
+    // state; only debug builds check it. Anything replaying a stored plan or
+    // taking outside input goes through try_apply.
+    fn apply_unchecked(&mut self, action: Action) {
+        debug_assert!(self.is_legal(action), "illegal action {}", action);
         match action {
             Action::Brew(id) => {
-                let order_idx = self
-                    .orders
-                    .iter()
-                    .position(|o| o.id == id && o.can_be_fulfilled_by(self.me.inventory))
-                    .unwrap();
-                let order = self.orders[order_idx];
+                let slot = self.order_slot(id).unwrap();
+                let order = self.orders[slot.0];
 
                 self.me.inventory += order.delta;
                 self.me.score += order.price;
+                self.me.brew_count += 1;
 
-                self.orders.remove(order_idx);
+                self.orders.remove(slot.0);
             }
             Action::Cast(id) => {
-                if let Some(spell) = self.me.spells.iter_mut().find(|s| s.id == id) {
-                    if spell.is_castable {
-                        self.me.inventory += spell.delta;
-                        spell.is_castable = false;
-                    }
-                    // dbg!(&self.me.spells);
-                }
+                let slot = self.spell_slot(id).unwrap();
+                let spell = &mut self.me.spells[slot.0];
+                self.me.inventory += spell.delta;
+                spell.is_castable = false;
             }
             Action::Learn(id) => {
-                if let Some(spell_idx) = self.tome.spells.iter().position(|s| s.id == id) {
-                    let spell = self.tome.spells[spell_idx];
-                    let delta = Vec4::new(spell.tome_index, 0, 0, 0);
-
-                    if (self.me.inventory - delta).is_non_neg() {
-                        let new_spell =
-                            Spell::new(spell.id + 1000, spell.is_repeatable, true, spell.delta);
-                        self.me.spells.push(new_spell);
-                        self.tome.remove_spell(spell)
-                    }
-                }
+                let slot = self.tome_slot(id).unwrap();
+                let spell = self.tome.spells[slot.0];
+
+                self.me.inventory.x -= spell.tome_index;
+                let space = MAX_INVENTORY - self.me.inventory.total();
+                self.me.inventory.x += spell.tax_count.min(space);
+
+                let new_spell =
+                    Spell::new(spell.id.learned(), spell.is_repeatable, true, spell.delta);
+                self.me.spells.push(new_spell);
+                self.me.update_cycle_value();
+                self.tome.remove_spell_at(slot)
             }
             Action::Rest => {
                 for spell in self.me.spells.iter_mut() {
@@ -315,21 +686,201 @@ impl State {
         }
     }
 
-    fn find_brewable_order(&self) -> Option<Order> {
+    fn average_cast_value(&self) -> f64 {
+        if self.me.spells.is_empty() {
+            return 0.0;
+        }
+        let total: f64 = self
+            .me
+            .spells
+            .iter()
+            .map(|s| inventory_value(s.delta).max(0.0))
+            .sum();
+        total / self.me.spells.len() as f64
+    }
+
+    fn evaluate(&self, weights: &Weights) -> f64 {
+        features::extract(self)
+            .iter()
+            .zip(weights.iter())
+            .map(|(&feature, &weight)| feature as f64 * weight)
+            .sum()
+    }
+
+    // the inventory is (nearly) full, holds high-tier ingredients, and none of the
+    // orders take them: plain search just shuffles ingredients around the cap
+    fn is_inventory_stuck(&self) -> bool {
+        let inventory = self.me.inventory;
+        inventory.total() >= MAX_INVENTORY - 1
+            && inventory.z + inventory.w > 0
+            && self.find_brewable_order().is_none()
+    }
+
+    // cast an owned downgrade spell if possible, otherwise learn the one the
+    // evaluator rates highest once learned, which counts what it adds to my
+    // rest cycle; the cheapest on ties
+    fn find_unjam_action(&self, weights: &Weights) -> Option<Action> {
+        let inventory = self.me.inventory;
+
+        let cast = self
+            .me
+            .spells
+            .iter()
+            .filter(|s| s.is_downgrade() && s.is_castable && s.can_be_afforded_by(inventory))
+            .max_by(|a, b| {
+                let a_value = inventory_value(inventory + a.delta);
+                let b_value = inventory_value(inventory + b.delta);
+                a_value.partial_cmp(&b_value).unwrap()
+            });
+        if let Some(spell) = cast {
+            return Some(Action::Cast(spell.id));
+        }
+
+        if self.me.spells.iter().any(|s| s.is_downgrade()) {
+            // already own one, it just needs a rest
+            return None;
+        }
+
+        self.tome
+            .spells
+            .iter()
+            .filter(|s| s.is_downgrade())
+            .rev()
+            .filter_map(|s| {
+                let mut learned = self.clone();
+                learned.try_apply(Action::Learn(s.id)).ok()?;
+                Some((learned.evaluate(weights), s.id))
+            })
+            .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+            .map(|(_, id)| Action::Learn(id))
+    }
+
+    // value of this state, counting an immediately available brew as done
+    fn value_with_brew(&self, weights: &Weights) -> f64 {
+        let value = self.evaluate(weights);
+        match self.find_brewable_order() {
+            Some(order) => {
+                let mut brewed = self.clone();
+                brewed.apply_unchecked(Action::Brew(order.id));
+                value.max(brewed.evaluate(weights))
+            }
+            None => value,
+        }
+    }
+
+    // the legal action leading to the best-valued state one step ahead
+    fn find_greedy_action(&self, weights: &Weights) -> Option<Action> {
+        self.get_possible_actions()
+            .into_iter()
+            .map(|action| {
+                let mut next = self.clone();
+                next.apply_unchecked(action);
+                (action, next.value_with_brew(weights))
+            })
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(action, _)| action)
+    }
+
+    // endgame planner for when no order can be brewed any more: every non
+    // tier-0 ingredient still held at the end is worth a rupee, so search the
+    // casts and rests of the last few turns for the most of them. None when
+    // nothing beats standing still.
+    fn find_liquidation_action(&self, turns_left: u32) -> Option<Action> {
+        let depth = (turns_left as usize).min(LIQUIDATION_DEPTH);
+        let standing = self.liquidation_value(0);
+        let mut best: Option<(Action, i32)> = None;
+        for action in self.get_possible_actions() {
+            let mut next = self.clone();
+            next.apply_unchecked(action);
+            let value = next.liquidation_value(depth.saturating_sub(1));
+            if value > best.map_or(standing, |(_, best_value)| best_value) {
+                best = Some((action, value));
+            }
+        }
+        best.map(|(action, _)| action)
+    }
+
+    // most end-bonus ingredients reachable within `depth` more casts and rests
+    fn liquidation_value(&self, depth: usize) -> i32 {
+        let inventory = self.me.inventory;
+        let held = inventory.y + inventory.z + inventory.w;
+        if depth == 0 {
+            return held;
+        }
+        self.get_possible_actions()
+            .into_iter()
+            .map(|action| {
+                let mut next = self.clone();
+                next.apply_unchecked(action);
+                next.liquidation_value(depth - 1)
+            })
+            .fold(held, i32::max)
+    }
+
+    // false only when `order` provably can't be brewed in `turns_left` turns,
+    // by the same bound the searches prune with
+    fn is_order_reachable(&self, order: Order, turns_left: u32) -> bool {
+        bound::min_turns(self, &order).is_some_and(|turns| turns <= turns_left as usize)
+    }
+
+    // value after playing greedily for `depth` turns: a second opinion on
+    // evaluate() that sees brews a few steps past the leaf
+    fn rollout_value(&self, weights: &Weights, depth: usize) -> f64 {
+        let mut state = self.clone();
+        for _ in 0..depth {
+            // brew as soon as possible, the same way Bot::think does
+            let action = match state.find_brewable_order() {
+                Some(order) => Some(Action::Brew(order.id)),
+                None => state.find_greedy_action(weights),
+            };
+            match action {
+                Some(action) => state.apply_unchecked(action),
+                None => break,
+            }
+        }
+        state.value_with_brew(weights)
+    }
+
+    // bit i is set when order i can be brewed, all orders checked in one pass
+    fn brewable_mask(&self) -> u32 {
+        let inventory = pack_guarded(self.me.inventory);
+        self.orders
+            .iter()
+            .enumerate()
+            .filter(|(_, o)| inventory.wrapping_sub(o.need) & LANE_GUARDS == LANE_GUARDS)
+            .fold(0, |mask, (i, _)| mask | 1 << i)
+    }
+
+    fn brewable_orders(&self) -> impl DoubleEndedIterator<Item = &Order> {
+        let mask = self.brewable_mask();
         self.orders
             .iter()
-            .find(|o| o.can_be_fulfilled_by(self.me.inventory))
+            .enumerate()
+            .filter(move |(i, _)| mask & 1 << i != 0)
+            .map(|(_, o)| o)
+    }
+
+    // the brewable order worth the most as a target, earliest on ties
+    fn find_brewable_order(&self) -> Option<Order> {
+        self.brewable_orders()
+            .rev()
+            .max_by(|a, b| a.target_value().partial_cmp(&b.target_value()).unwrap())
             .cloned()
     }
 
-    fn read_from_io() -> Self {
-        // std::fs::read("C:/Users/Matharu/Desktop/Development/Workspace/codingame/fall-challenge-2020-rust/input.txt");
-        // let file = std::fs::File::open("C:/Users/Matharu/Desktop/Development/Workspace/codingame/fall-challenge-2020-rust/input.txt").unwrap();
-        // let mut reader = io::BufReader::new(file);
+    // a stale order only counts as a search goal when no fresh order is left
+    fn is_stale_target(&self, order: Order) -> bool {
+        order.is_stale() && self.orders.iter().any(|o| !o.is_stale())
+    }
+
+    fn parse(turn_input: &str) -> Self {
+        Self::parse_with_enemy(turn_input).0
+    }
 
-        let mut input_line = String::new();
-        io::stdin().read_line(&mut input_line).unwrap();
-        let action_count = parse_input!(input_line, i32); // the number of spells and recipes in play
+    // also returns the opponent as a Player, for simulating its side
+    fn parse_with_enemy(turn_input: &str) -> (Self, Player) {
+        let mut lines = turn_input.lines();
+        let action_count = parse_input!(lines.next().unwrap(), i32); // the number of spells and recipes in play
 
         let mut orders = Vec::with_capacity(5);
         let mut my_spells = Vec::new();
@@ -337,10 +888,9 @@ impl State {
         let mut tome = Vec::new();
 
         for _ in 0..action_count as usize {
-            let mut input_line = String::new();
-            io::stdin().read_line(&mut input_line).unwrap();
-            let inputs = input_line.split(" ").collect::<Vec<_>>();
-            let action_id = parse_input!(inputs[0], i32); // the unique ID of this spell or recipe
+            let input_line = lines.next().unwrap();
+            let inputs = input_line.split(' ').collect::<Vec<_>>();
+            let action_id = EngineId(parse_input!(inputs[0], i32)); // the unique ID of this spell or recipe
             let action_type = inputs[1].trim().to_string(); // in the first league: BREW; later: CAST, OPPONENT_CAST, LEARN, BREW
 
             let x = parse_input!(inputs[2], i32); // tier-0 ingredient change
@@ -356,7 +906,9 @@ impl State {
 
             match action_type.as_str() {
                 "BREW" => {
-                    orders.push(Order::new(action_id, price, delta));
+                    let mut order = Order::new(action_id, price, delta);
+                    order.bonus = tome_index;
+                    orders.push(order);
                 }
                 "CAST" => {
                     my_spells.push(Spell::new(action_id, repeatable, castable, delta));
@@ -373,10 +925,15 @@ impl State {
             }
         }
 
-        // for i in 0..2 as usize {
-        let mut input_line = String::new();
-        // io::stdin()
-        io::stdin().read_line(&mut input_line).unwrap();
+        // the judge's listing order isn't guaranteed; anything that walks these
+        // vectors (hashing, move order, order slots) should see the same board
+        // the same way
+        orders.sort_by_key(|o| o.id);
+        my_spells.sort_by_key(|s| s.id);
+        enemy_spells.sort_by_key(|s| s.id);
+        tome.sort_by_key(|s| s.tome_index);
+
+        let input_line = lines.next().unwrap();
         let inputs = input_line.split(' ').collect::<Vec<_>>();
         let x = parse_input!(inputs[0], i32); // tier-0 ingredients in inventory
         let y = parse_input!(inputs[1], i32);
@@ -385,137 +942,4432 @@ impl State {
         let score = parse_input!(inputs[4], i32); // amount of rupees
 
         let me = Player::new(score, Vec4::new(x, y, z, w), my_spells);
-        // }
 
-        State::new(me, MagicTome::new(tome), orders)
+        let input_line = lines.next().unwrap();
+        let inputs = input_line.split(' ').collect::<Vec<_>>();
+        let x = parse_input!(inputs[0], i32);
+        let y = parse_input!(inputs[1], i32);
+        let z = parse_input!(inputs[2], i32);
+        let w = parse_input!(inputs[3], i32);
+        let enemy_score = parse_input!(inputs[4], i32);
+        let enemy = Player::new(enemy_score, Vec4::new(x, y, z, w), enemy_spells);
+
+        let mut state = State::new(me, MagicTome::new(tome), orders);
+        state.enemy_score = enemy.score;
+        state.enemy_cycle_value = enemy.cycle_value;
+        (state, enemy)
     }
 }
 
-#[derive(Copy, Clone)]
-struct Bot;
+// (inventory, castable mask, spell count) -> legal Cast and Rest actions
+type CastCache = HashMap<(Vec4, u64, usize), Vec<Action>>;
 
-impl Bot {
-    fn new() -> Self {
-        Self {}
-    }
+// (order ids, spell ids, tome size) -> (inventory, score, castable mask,
+// depth) of every state linked so far; states in one bucket differ only in
+// those four
+type DominanceIndex = HashMap<(Vec<EngineId>, Vec<EngineId>, usize), Vec<(Vec4, i32, u64, usize)>>;
 
-    fn bfs(self, start_instant: &Instant, state: &State) -> Vec<Action> {
-        const MAX_DURATION: Duration = Duration::from_millis(1000);
-        let mut queue = LinkedList::<State>::new();
-        // let mut queue = VecDeque::<State>::new();
-        let mut visited = HashSet::<State>::new();
-        let mut predecessor = HashMap::<State, State>::new();
-        let mut pred_action = HashMap::<State, Action>::new();
+// Spells as edges between inventory profiles. Between two rests each spell
+// fires at most once, so one rest cycle is a set of spells that can be cast in
+// some order starting from an empty inventory; the profile it ends on is the
+// cycle's net production, and the cycle takes one turn per cast plus the rest.
+mod synergy {
+    use super::{inventory_value, Vec4, MAX_INVENTORY};
 
-        let initial_state = state.clone();
-        queue.push_back(state.clone());
-        pred_action.insert(state.clone(), Action::Wait);
-        visited.insert(state.clone());
-        let mut iterations = 0;
-        while let Some(current_state) = queue.pop_front() {
-            if start_instant.elapsed() > MAX_DURATION {
-                // time over
-                break;
-            }
-            iterations += 1;
+    // beyond this many spells the subset walk gets too slow for a turn
+    const MAX_CYCLE_SPELLS: usize = 12;
 
-            if current_state.find_brewable_order().is_some() {
-                let mut path = Vec::<Action>::new();
-                let mut curr_state = &current_state;
-                while curr_state != &initial_state {
-                    let action = pred_action.get(curr_state).expect("pred action not found");
-                    let last_state = predecessor.get(curr_state).expect("prev state not found");
-                    path.push(*action);
-                    curr_state = last_state;
-                }
+    // best net value per turn over every executable rest cycle
+    pub fn best_cycle_value(deltas: &[Vec4]) -> f64 {
+        let mut deltas = deltas.to_vec();
+        deltas.sort_by(|a, b| {
+            inventory_value(*b)
+                .partial_cmp(&inventory_value(*a))
+                .unwrap()
+        });
+        deltas.truncate(MAX_CYCLE_SPELLS);
+        let mut reachable = vec![false; 1 << deltas.len()];
+        reachable[0] = true;
 
-                println!("{} game states visited", iterations);
-                return path;
+        let mut best = 0.0f64;
+        for mask in 0..reachable.len() {
+            if !reachable[mask] {
+                continue;
             }
+            // casting order doesn't change where a set of casts ends up
+            let profile = deltas
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| mask & 1 << i != 0)
+                .fold(Vec4::zero(), |sum, (_, &delta)| sum + delta);
+            let turns = mask.count_ones() as f64 + 1.0;
+            best = best.max(inventory_value(profile) / turns);
 
-            let curr_state = &current_state;
-
-            for action in current_state.get_possible_actions() {
-                let mut next = curr_state.clone();
-                next.apply(action);
-
-                if !visited.contains(&next) {
-                    queue.push_back(next.clone());
-                    pred_action.entry(next.clone()).or_insert(action);
-                    predecessor
-                        .entry(next.clone())
-                        .or_insert(curr_state.clone());
-                    visited.insert(next.clone());
+            for (i, &delta) in deltas.iter().enumerate() {
+                let next = profile + delta;
+                if mask & 1 << i == 0 && next.is_non_neg() && next.total() <= MAX_INVENTORY {
+                    reachable[mask | 1 << i] = true;
                 }
             }
         }
+        best
+    }
+}
+
+type Weights = [f64; features::N];
+
+// Feature vector shared by the evaluator and any offline tooling.
+//
+// Layout, versioned by features::VERSION:
+//   0       my score
+//   1..=4   inventory count per tier
+//   5       castable spells x average cast value (what a Rest buys back)
+//   6..=10  ingredients missing for each order slot, MAX_INVENTORY if the slot is empty
+//   11..=13 owned producer / converter / downgrade spell counts
+//   14      1.0 if some order can be brewed right now
+//   15      1.0 if the inventory is at the cap
+//   16      net value per turn of my best rest cycle (see synergy)
+//   17      free inventory slots x castable producer spells (room to cast into)
+//   18..=20 1.0 for the game phase by potions brewed, see phase_of
+mod features {
+    use super::{
+        is_downgrade, phase_of, State, CASTABLE_WEIGHT, CYCLE_WEIGHT, MAX_INVENTORY, SCORE_WEIGHT,
+        SLACK_WEIGHT, TIER_VALUES,
+    };
+    use std::ops::Range;
+
+    // bumped whenever indices move or features are appended, so weights
+    // tuned against an older layout can be told apart (Config's
+    // weight_version); 1 had 16 features, 2 had 18
+    pub const VERSION: u32 = 3;
+    pub const N: usize = 21;
+    const ORDER_SLOTS: usize = 5;
 
-        Vec::new()
+    pub const WEIGHTS: [f64; N] = [
+        SCORE_WEIGHT,
+        TIER_VALUES[0],
+        TIER_VALUES[1],
+        TIER_VALUES[2],
+        TIER_VALUES[3],
+        CASTABLE_WEIGHT,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        CYCLE_WEIGHT,
+        SLACK_WEIGHT,
+        0.0,
+        0.0,
+        0.0,
+    ];
+
+    // named groups of indices, for reading an evaluation term by term
+    const TERMS: [(&str, Range<usize>); 14] = [
+        ("score", 0..1),
+        ("inventory", 1..5),
+        ("castable", 5..6),
+        ("order slot 0", 6..7),
+        ("order slot 1", 7..8),
+        ("order slot 2", 8..9),
+        ("order slot 3", 9..10),
+        ("order slot 4", 10..11),
+        ("spell mix", 11..14),
+        ("brew ready", 14..15),
+        ("inventory full", 15..16),
+        ("rest cycle", 16..17),
+        ("slack", 17..18),
+        ("phase", 18..21),
+    ];
+
+    // what each term adds to State::evaluate; the values sum to it
+    pub fn decompose(state: &State, weights: &[f64; N]) -> Vec<(&'static str, f64)> {
+        let features = extract(state);
+        TERMS
+            .iter()
+            .map(|(name, range)| {
+                let value = range.clone().map(|i| features[i] as f64 * weights[i]).sum();
+                (*name, value)
+            })
+            .collect()
     }
 
-    fn think(self, start_instant: &Instant, state: &State) -> Action {
-        if let Some(order) = state.find_brewable_order() {
-            return Action::Brew(order.id);
+    pub fn extract(state: &State) -> [f32; N] {
+        let mut features = [0.0; N];
+        let inventory = state.me.inventory;
+
+        features[0] = state.me.score as f32;
+        for (tier, &count) in inventory.to_array().iter().enumerate() {
+            features[1 + tier] = count as f32;
         }
 
-        let actions: Vec<Action> = self.bfs(start_instant, state).into_iter().rev().collect();
+        let castable = state.me.spells.iter().filter(|s| s.is_castable).count();
+        features[5] = (castable as f64 * state.average_cast_value()) as f32;
 
-        // for action in actions.iter() {
-        //     println!("{}", action.to_string());
-        // }
+        for slot in 0..ORDER_SLOTS {
+            features[6 + slot] = match state.orders.get(slot) {
+                Some(order) => (inventory + order.delta)
+                    .to_array()
+                    .iter()
+                    .map(|&left| (-left).max(0))
+                    .sum::<i32>() as f32,
+                None => MAX_INVENTORY as f32,
+            };
+        }
 
-        if let Some(&action) = actions.first() {
-            return action;
+        for spell in state.me.spells.iter() {
+            let archetype = if spell.delta.is_non_neg() {
+                0
+            } else if is_downgrade(spell.delta) {
+                2
+            } else {
+                1
+            };
+            features[11 + archetype] += 1.0;
         }
 
-        // println!("Time took: {} ms", start_instant.elapsed().as_millis());
+        if state.find_brewable_order().is_some() {
+            features[14] = 1.0;
+        }
+        if inventory.total() >= MAX_INVENTORY {
+            features[15] = 1.0;
+        }
+        features[16] = state.me.cycle_value as f32 / 100.0;
 
-        println!("no actions computed, taking first possible action");
+        let producers = state
+            .me
+            .spells
+            .iter()
+            .filter(|s| s.is_castable && s.delta.is_non_neg())
+            .count();
+        features[17] = ((MAX_INVENTORY - inventory.total()) as usize * producers) as f32;
+        features[18 + phase_of(state.me.brew_count)] = 1.0;
 
-        state.get_possible_actions().first().cloned().unwrap()
+        features
     }
 }
 
-fn main() {
-    let bot = Bot::new();
-
-    let state = State::read_from_io();
+// Learn economics of every spell in the tome, for checking the learn policy by hand.
+fn print_tome_report(state: &State) {
+    let mut deltas: Vec<Vec4> = state.me.spells.iter().map(|s| s.delta).collect();
+    let current_cycle = synergy::best_cycle_value(&deltas);
 
-    let mut total_duration = Duration::new(0, 0);
-    for _ in 0..100 {
-        let turn_state = state.clone();
-       // let turn_state = State::read_from_io();
+    println!(
+        "{:>4} {:>16} {:>5} {:>7} {:>6} {:>8} {:>6}",
+        "id", "delta", "tax", "refund", "value", "payback", "cycle"
+    );
+    for spell in state.tome.spells.iter() {
+        deltas.push(spell.delta);
+        let cycle_gain = synergy::best_cycle_value(&deltas) - current_cycle;
+        deltas.pop();
 
-        let start_instant = Instant::now();
-        let action = bot.think(&start_instant, &turn_state);
-        total_duration += start_instant.elapsed();
+        let payback = match spell.payback_turns() {
+            Some(turns) => turns.to_string(),
+            None => "never".to_string(),
+        };
+        println!(
+            "{:>4} {:>16} {:>5} {:>7} {:>6.1} {:>8} {:>+6.2}",
+            spell.id,
+            format!("{:?}", spell.delta),
+            spell.tome_index,
+            spell.tax_count,
+            spell.cast_value(),
+            payback,
+            cycle_gain
+        );
+    }
+}
 
-        println!("Action {}", action.to_string());
+// reads one turn worth of judge input: the action count line, the actions,
+// and both players' inventory lines; None once the input has ended
+fn read_turn_input(reader: &mut impl BufRead) -> Option<String> {
+    let mut turn_input = String::new();
+    while turn_input.trim().is_empty() {
+        turn_input.clear();
+        if reader.read_line(&mut turn_input).ok()? == 0 {
+            return None;
+        }
+    }
+    let action_count = parse_input!(turn_input, usize);
+    for _ in 0..action_count + 2 {
+        reader.read_line(&mut turn_input).ok()?;
     }
+    Some(turn_input)
+}
 
-     println!("Average time taken: {:?}", total_duration / 100)
+#[derive(Debug, Clone)]
+struct Config {
+    // search time on the first turn, when the judge allows a full second
+    first_turn_budget: Duration,
+    // search time on every later turn, under the judge's 50ms
+    time_budget: Duration,
+    // print per-turn search diagnostics to stderr
+    explain: bool,
+    // evaluator weights, indexed like features::extract
+    weights: Weights,
+    // log turns where a rollout disagrees with the static evaluation
+    cross_check: bool,
+    // stop each search after this many nodes even if the clock says otherwise;
+    // searches are also capped at what ClockCalibration says fits their budget
+    max_nodes: usize,
+    // best plans kept under distinct first actions, for explain and the timeline
+    top_plans: usize,
+    search: SearchMode,
+    // states kept per depth in SearchMode::Beam
+    beam_width: usize,
+    // evaluation spread, in evaluator units, over which SearchMode::StochasticBeam
+    // favours one survivor e times more than another
+    beam_temperature: f64,
+    // MCTS playouts: rollout::playout with `rollout_bias` instead of the
+    // slower greedy State::rollout_value
+    biased_rollouts: bool,
+    rollout_bias: rollout::Bias,
+    mcts: search::mcts::Params,
+    // ablation switches: learning stops from this turn on, and the sniping
+    // alarm's denial brews can be turned off
+    no_learn_after: u32,
+    denial_brews: bool,
+    // play the rest of last turn's plan without searching while it still works
+    reuse_plans: bool,
+    // SearchMode::Bfs picks up last turn's tree when it can, see Kept
+    reuse_tree: bool,
+    // brews a tree search plans for before it stops at a goal; past 1 it
+    // keeps looking for plans that chain several brews
+    brew_target: u32,
+    // beam survivors carried over to the next turn's beam, 0 to start cold
+    warm_start: usize,
+    // let bfs skip states some state it already reached dominates
+    dominance: bool,
+    // let dfbnb play a brew available at its horizon, see BranchAndBound::extend_brew
+    quiescence: bool,
+    // order expansions by the History of earlier plans
+    history: bool,
+    // let bfs branch on macro_chains as well as single actions
+    macros: bool,
+    // fraction of a node's value lost per turn of depth, so the same brew
+    // scores higher the sooner it comes
+    tempo_discount: f64,
+    // mixed into every RNG seed, so randomized searches can be rerun differently
+    seed: u64,
+    // root-parallel workers, see Bot::root_parallel
+    #[cfg(feature = "parallel")]
+    threads: usize,
 }
 
-fn main2() {
-    let bot = Bot::new();
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum SearchMode {
+    // exhaustive, level by level
+    Bfs,
+    // the beam_width best states of each level
+    Beam,
+    // beams restarted at doubling widths while the budget lasts
+    Chokudai,
+    // a beam sampling its survivors by exp(value / beam_temperature)
+    StochasticBeam,
+    // search::mcts, for as long as the budget lasts
+    Mcts,
+    // depth-first with a growing depth limit
+    Iddfs,
+    // depth-first branch and bound on the score at a fixed horizon
+    Dfbnb,
+    // A* toward each order, then the best rupees per turn
+    AStar,
+    // the most valuable frontier state first
+    BestFirst,
+    // search::minimax against the parsed opponent
+    Minimax,
+    // the same with alpha-beta pruning
+    AlphaBeta,
+    // bfs for a share of the budget, then search::annealing on its plan
+    Annealing,
+    // search::genetic, for as long as the budget lasts
+    Genetic,
+    // rolling horizon: search::genetic seeded with last turn's plan, shifted
+    Rhea,
+    // a narrow beam, astar and a short duel, then a weighted vote on the action
+    Ensemble,
+    // bfs, with the opponent brewing the dearest order it can every move
+    Paranoid,
+}
 
-    let state = State::read_from_io();
-    let mut turn = 0;
-    let mut total_duration = Duration::new(0, 0);
-    for _ in 0..100 {
-        let turn_state = state.clone();
-        let start_instant = Instant::now();
+const DEFAULT_BEAM_WIDTH: usize = 200;
+const DEFAULT_TEMPO_DISCOUNT: f64 = 0.01;
+const DEFAULT_WARM_START: usize = 20;
+// what a plan's action earns in History, so it survives a few halvings
+const HISTORY_CREDIT: u32 = 8;
+// half a rupee
+const DEFAULT_BEAM_TEMPERATURE: f64 = 0.5 * SCORE_WEIGHT;
+// fixed so that a replayed game samples the same beams
+const STOCHASTIC_BEAM_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+const ROLLOUT_SEED: u64 = 0xd1b5_4a32_d192_ed03;
+// SearchMode::Chokudai stops doubling here even with budget left
+const CHOKUDAI_MAX_WIDTH: usize = 4096;
+// share of the turn budget Bot::estimate_races may spend (from RACE_SHARE
+// early or with a clear margin up to RACE_MAX_SHARE in a close endgame), how
+// far ahead it looks, and how many turns of lead make a race about 73% ours
+const RACE_SHARE: f64 = 0.1;
+const RACE_MAX_SHARE: f64 = 0.3;
+const RACE_HORIZON: u32 = 12;
+const RACE_SPREAD: f64 = 1.0;
+// claim, in percent, left on an order the opponent can brew next turn and we
+// can't brew this one, see Bot::concede_sniped_orders
+const SNIPED_CLAIM: u8 = 20;
+// turns a RaceCache entry stays usable
+const RACE_CACHE_TURNS: u32 = 3;
+// most moves per player search::minimax deepens to
+const MINIMAX_MAX_PLIES: usize = 6;
+// moves Bot::dfbnb looks ahead when the caller sets no tighter limit
+const DFBNB_HORIZON: usize = 10;
+// part of the budget SearchMode::Annealing spends on the bfs seed plan
+const ANNEALING_SEED_SHARE: f64 = 0.5;
+// SearchMode::Ensemble's planners and their votes; the beam's vote breaks a
+// three-way split, the other two together outvote it
+const ENSEMBLE_BEAM_WIDTH: usize = 50;
+const ENSEMBLE_BEAM_VOTE: f64 = 1.2;
+const ENSEMBLE_ASTAR_VOTE: f64 = 1.0;
+const ENSEMBLE_DUEL_PLIES: usize = 2;
+const ENSEMBLE_DUEL_VOTE: f64 = 1.0;
 
-        let action = match turn {
-            0..=6 => Action::Learn(turn_state.tome.spells.first().unwrap().id),
-            _ => bot.think(&start_instant, &turn_state),
-        };
+impl Config {
+    fn new(first_turn_budget: Duration, time_budget: Duration) -> Self {
+        Self {
+            first_turn_budget,
+            time_budget,
+            explain: false,
+            weights: features::WEIGHTS,
+            cross_check: false,
+            max_nodes: usize::MAX,
+            top_plans: 0,
+            search: SearchMode::Bfs,
+            beam_width: DEFAULT_BEAM_WIDTH,
+            beam_temperature: DEFAULT_BEAM_TEMPERATURE,
+            biased_rollouts: false,
+            rollout_bias: rollout::Bias::default(),
+            mcts: search::mcts::Params::default(),
+            no_learn_after: MAX_TURNS,
+            denial_brews: true,
+            reuse_plans: true,
+            reuse_tree: true,
+            brew_target: 1,
+            warm_start: DEFAULT_WARM_START,
+            dominance: true,
+            quiescence: true,
+            history: true,
+            macros: false,
+            tempo_discount: DEFAULT_TEMPO_DISCOUNT,
+            seed: 0,
+            #[cfg(feature = "parallel")]
+            threads: 1,
+        }
+    }
 
-        // let action = bot.think(&start_instant, &turn_state);
-        total_duration += start_instant.elapsed();
-        turn += 1;
-        println!("Action {}", action.to_string());
+    // `key = value` lines, `#` starts a comment:
+    //   time_budget_ms = 40
+    //   first_turn_budget_ms = 1000
+    //   explain = true
+    //   cross_check = true
+    //   max_nodes = 50000
+    //   top_plans = 3
+    //   search = beam
+    //   beam_width = 200
+    //   beam_temperature = 5.0
+    //   rollout = biased
+    //   rollout.brew = 50.0
+    //   mcts.exploration = 1.4
+    //   mcts.widen_depth = 3
+    //   no_learn_after = 20
+    //   denial_brews = false
+    //   reuse_plans = true
+    //   reuse_tree = false
+    //   brew_target = 2
+    //   warm_start = 20
+    //   dominance = true
+    //   quiescence = false
+    //   history = true
+    //   macros = true
+    //   tempo_discount = 0.01
+    //   seed = 7
+    //   threads = 4 (parallel builds only)
+    //   weight_version = 3 (features::VERSION the weights were tuned on; a
+    //   mismatch is reported)
+    //   weight.5 = 0.7
+    fn apply_overrides(&mut self, text: &str) {
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=').map(str::trim);
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => {
+                    eprintln!("config: ignoring malformed line '{}'", line);
+                    continue;
+                }
+            };
+            if !self.apply_override(key, value) {
+                eprintln!("config: ignoring '{}'", line);
+            }
+        }
+        // only the SearchTree searches chain brews toward a goal
+        let chains = matches!(
+            self.search,
+            SearchMode::Bfs
+                | SearchMode::Beam
+                | SearchMode::StochasticBeam
+                | SearchMode::Chokudai
+                | SearchMode::BestFirst
+                | SearchMode::Paranoid
+        );
+        if self.brew_target > 1 && !chains {
+            eprintln!(
+                "config: brew_target needs a tree search, not {:?}; using 1",
+                self.search
+            );
+            self.brew_target = 1;
+        }
+    }
+
+    fn apply_override(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "time_budget_ms" => match value.parse() {
+                Ok(ms) => self.time_budget = Duration::from_millis(ms),
+                Err(_) => return false,
+            },
+            "first_turn_budget_ms" => match value.parse() {
+                Ok(ms) => self.first_turn_budget = Duration::from_millis(ms),
+                Err(_) => return false,
+            },
+            "explain" => match value.parse() {
+                Ok(explain) => self.explain = explain,
+                Err(_) => return false,
+            },
+            "cross_check" => match value.parse() {
+                Ok(cross_check) => self.cross_check = cross_check,
+                Err(_) => return false,
+            },
+            "max_nodes" => match value.parse() {
+                Ok(max_nodes) => self.max_nodes = max_nodes,
+                Err(_) => return false,
+            },
+            "top_plans" => match value.parse() {
+                Ok(top_plans) => self.top_plans = top_plans,
+                Err(_) => return false,
+            },
+            "search" => match value {
+                "bfs" => self.search = SearchMode::Bfs,
+                "beam" => self.search = SearchMode::Beam,
+                "chokudai" => self.search = SearchMode::Chokudai,
+                "stochastic_beam" => self.search = SearchMode::StochasticBeam,
+                "mcts" => self.search = SearchMode::Mcts,
+                "iddfs" => self.search = SearchMode::Iddfs,
+                "dfbnb" => self.search = SearchMode::Dfbnb,
+                "astar" => self.search = SearchMode::AStar,
+                "best_first" => self.search = SearchMode::BestFirst,
+                "minimax" => self.search = SearchMode::Minimax,
+                "alpha_beta" => self.search = SearchMode::AlphaBeta,
+                "paranoid" => self.search = SearchMode::Paranoid,
+                "annealing" => self.search = SearchMode::Annealing,
+                "genetic" => self.search = SearchMode::Genetic,
+                "rhea" => self.search = SearchMode::Rhea,
+                "ensemble" => self.search = SearchMode::Ensemble,
+                _ => return false,
+            },
+            "beam_width" => match value.parse() {
+                Ok(width) if width > 0 => self.beam_width = width,
+                _ => return false,
+            },
+            "rollout" => match value {
+                "greedy" => self.biased_rollouts = false,
+                "biased" => self.biased_rollouts = true,
+                _ => return false,
+            },
+            "rollout.brew" | "rollout.cast" | "rollout.rest" => match value.parse() {
+                Ok(weight) if weight >= 0.0 => match key {
+                    "rollout.brew" => self.rollout_bias.brew = weight,
+                    "rollout.cast" => self.rollout_bias.cast = weight,
+                    _ => self.rollout_bias.rest = weight,
+                },
+                _ => return false,
+            },
+            "no_learn_after" => match value.parse() {
+                Ok(turn) => self.no_learn_after = turn,
+                Err(_) => return false,
+            },
+            "denial_brews" => match value.parse() {
+                Ok(denial) => self.denial_brews = denial,
+                Err(_) => return false,
+            },
+            "reuse_plans" => match value.parse() {
+                Ok(reuse) => self.reuse_plans = reuse,
+                Err(_) => return false,
+            },
+            "reuse_tree" => match value.parse() {
+                Ok(reuse) => self.reuse_tree = reuse,
+                Err(_) => return false,
+            },
+            "brew_target" => match value.parse() {
+                Ok(target) if target > 0 => self.brew_target = target,
+                _ => return false,
+            },
+            "warm_start" => match value.parse() {
+                Ok(warm_start) => self.warm_start = warm_start,
+                Err(_) => return false,
+            },
+            "dominance" => match value.parse() {
+                Ok(dominance) => self.dominance = dominance,
+                Err(_) => return false,
+            },
+            "quiescence" => match value.parse() {
+                Ok(quiescence) => self.quiescence = quiescence,
+                Err(_) => return false,
+            },
+            "history" => match value.parse() {
+                Ok(history) => self.history = history,
+                Err(_) => return false,
+            },
+            "macros" => match value.parse() {
+                Ok(macros) => self.macros = macros,
+                Err(_) => return false,
+            },
+            "tempo_discount" => match value.parse() {
+                Ok(discount) if (0.0..1.0).contains(&discount) => self.tempo_discount = discount,
+                _ => return false,
+            },
+            "seed" => match value.parse() {
+                Ok(seed) => self.seed = seed,
+                Err(_) => return false,
+            },
+            #[cfg(feature = "parallel")]
+            "threads" => match value.parse() {
+                Ok(threads) if threads > 0 => self.threads = threads,
+                _ => return false,
+            },
+            "mcts.exploration" => match value.parse() {
+                Ok(exploration) if exploration >= 0.0 => self.mcts.exploration = exploration,
+                _ => return false,
+            },
+            "mcts.rollout_depth" => match value.parse() {
+                Ok(depth) => self.mcts.rollout_depth = depth,
+                Err(_) => return false,
+            },
+            "mcts.prior_weight" => match value.parse() {
+                Ok(weight) if weight >= 0.0 => self.mcts.prior_weight = weight,
+                _ => return false,
+            },
+            "mcts.widen_depth" => match value.parse() {
+                Ok(depth) => self.mcts.widen_depth = depth,
+                Err(_) => return false,
+            },
+            "mcts.widen_base" => match value.parse() {
+                Ok(base) if base > 0.0 => self.mcts.widen_base = base,
+                _ => return false,
+            },
+            "mcts.widen_exponent" => match value.parse() {
+                Ok(exponent) if exponent >= 0.0 => self.mcts.widen_exponent = exponent,
+                _ => return false,
+            },
+            "beam_temperature" => match value.parse() {
+                Ok(temperature) if temperature > 0.0 => self.beam_temperature = temperature,
+                _ => return false,
+            },
+            "weight_version" => return value.parse() == Ok(features::VERSION),
+            _ => {
+                let index = key
+                    .strip_prefix("weight.")
+                    .and_then(|i| i.parse::<usize>().ok());
+                match (index, value.parse()) {
+                    (Some(index), Ok(weight)) if index < features::N => {
+                        self.weights[index] = weight
+                    }
+                    _ => return false,
+                }
+            }
+        }
+        true
     }
 
-    println!("Average time taken: {:?}", total_duration / 101)
+    fn from_args(args: &[String]) -> Self {
+        let mut config = Self::default();
+        for arg in args {
+            match arg.as_str() {
+                "--explain" => config.explain = true,
+                "--cross-check" => config.cross_check = true,
+                "--beam" => config.search = SearchMode::Beam,
+                "--chokudai" => config.search = SearchMode::Chokudai,
+                "--stochastic-beam" => config.search = SearchMode::StochasticBeam,
+                "--mcts" => config.search = SearchMode::Mcts,
+                "--iddfs" => config.search = SearchMode::Iddfs,
+                "--dfbnb" => config.search = SearchMode::Dfbnb,
+                "--astar" => config.search = SearchMode::AStar,
+                "--best-first" => config.search = SearchMode::BestFirst,
+                "--minimax" => config.search = SearchMode::Minimax,
+                "--alpha-beta" => config.search = SearchMode::AlphaBeta,
+                "--paranoid" => config.search = SearchMode::Paranoid,
+                "--annealing" => config.search = SearchMode::Annealing,
+                "--genetic" => config.search = SearchMode::Genetic,
+                "--rhea" => config.search = SearchMode::Rhea,
+                "--ensemble" => config.search = SearchMode::Ensemble,
+                _ => {}
+            }
+        }
+        config
+    }
 }
+
+// search::dijkstra::turns_to_orders for the opponent, kept across turns by
+// everything that search reads: their inventory and spells, and the orders.
+// An opponent that stalls or keeps returning to the same position gets its
+// race answered without searching again.
+type RaceKey = (Vec4, u64, Vec<EngineId>, Vec<EngineId>);
+
+#[derive(Debug, Default, Clone)]
+struct RaceCache {
+    // turns_left when stored, and the result
+    entries: HashMap<RaceKey, (u32, HashMap<EngineId, u32>)>,
+}
+
+impl RaceCache {
+    fn key(rival: &State) -> RaceKey {
+        (
+            rival.me.inventory,
+            rival.castable_mask(),
+            rival.me.spells.iter().map(|s| s.id).collect(),
+            rival.orders.iter().map(|o| o.id).collect(),
+        )
+    }
+
+    fn get(&mut self, rival: &State, turns_left: u32) -> Option<HashMap<EngineId, u32>> {
+        self.entries
+            .retain(|_, (stored, _)| *stored < turns_left + RACE_CACHE_TURNS);
+        self.entries
+            .get(&Self::key(rival))
+            .map(|(_, turns)| turns.clone())
+    }
+
+    fn insert(&mut self, rival: &State, turns_left: u32, turns: &HashMap<EngineId, u32>) {
+        self.entries
+            .insert(Self::key(rival), (turns_left, turns.clone()));
+    }
+}
+
+// Best continuation value found under each legal first action.
+#[derive(Debug, Default)]
+struct RootValues {
+    values: Vec<(Action, f64)>,
+}
+
+impl RootValues {
+    fn record(&mut self, root: Action, value: f64) {
+        match self.values.iter_mut().find(|(action, _)| *action == root) {
+            Some(entry) => entry.1 = entry.1.max(value),
+            None => self.values.push((root, value)),
+        }
+    }
+
+    fn sorted(&self) -> Vec<(Action, f64)> {
+        let mut values = self.values.clone();
+        values.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        values
+    }
+
+    fn explain(&self) {
+        for (action, value) in self.sorted() {
+            eprintln!("  {:<10} {:>8.2}", action.to_string(), value);
+        }
+    }
+}
+
+// How often each action sat at each depth of the plans the searches settled
+// on, kept in Bot across turns with Config::history. Expansions try the
+// actions with the most credit first, after the brews. Halved every turn, so
+// habits from an earlier board fade.
+#[derive(Debug, Default, Clone)]
+struct History {
+    scores: HashMap<(usize, Action), u32>,
+}
+
+impl History {
+    fn credit(&mut self, plan: &[Action]) {
+        for (depth, &action) in plan.iter().enumerate() {
+            *self.scores.entry((depth, action)).or_default() += HISTORY_CREDIT;
+        }
+    }
+
+    fn age(&mut self) {
+        self.scores.retain(|_, score| {
+            *score /= 2;
+            *score > 0
+        });
+    }
+
+    fn score(&self, depth: usize, action: Action) -> u32 {
+        self.scores.get(&(depth, action)).copied().unwrap_or(0)
+    }
+
+    // stable, so actions without credit keep expand's order
+    fn order(&self, depth: usize, children: &mut [(Action, State, Option<LastCast>)]) {
+        children.sort_by_key(|&(action, ..)| {
+            let brew = matches!(action, Action::Brew(_));
+            (!brew, std::cmp::Reverse(self.score(depth, action)))
+        });
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+struct SearchStats {
+    nodes: usize,
+    max_depth: usize,
+    // children skipped as reorderings of commuting casts
+    pruned: usize,
+    // children skipped as dominated by a state already linked
+    dominated: usize,
+    // alpha-beta siblings skipped once the window closed
+    cutoffs: usize,
+    // largest sizes reached by the search's containers of states
+    peak_visited: usize,
+    peak_arena: usize,
+    peak_frontier: usize,
+}
+
+// a visited state is held by the visited set and as a key in both
+// predecessor maps, plus once more as some other state's predecessor
+const VISITED_STATE_COPIES: usize = 4;
+
+impl SearchStats {
+    fn note_visited(&mut self, len: usize) {
+        self.peak_visited = self.peak_visited.max(len);
+    }
+
+    fn note_arena(&mut self, len: usize) {
+        self.peak_arena = self.peak_arena.max(len);
+    }
+
+    fn note_frontier(&mut self, len: usize) {
+        self.peak_frontier = self.peak_frontier.max(len);
+    }
+
+    // rough heap use at the peaks, for states of about `state_bytes` each
+    fn estimated_bytes(&self, state_bytes: usize) -> usize {
+        let states =
+            self.peak_visited * VISITED_STATE_COPIES + self.peak_arena + self.peak_frontier;
+        states * state_bytes
+    }
+}
+
+// A full plan and the value of the state it leads to.
+#[derive(Debug, Clone)]
+struct RankedPlan {
+    plan: Vec<Action>,
+    value: f64,
+}
+
+impl fmt::Display for RankedPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let plan: Vec<String> = self.plan.iter().map(Action::to_string).collect();
+        write!(f, "{:>8.2}  {}", self.value, plan.join(", "))
+    }
+}
+
+#[derive(Debug)]
+struct SearchResult {
+    plan: Vec<Action>,
+    root_values: RootValues,
+    stats: SearchStats,
+    // best first, at most Config::top_plans of them
+    alternatives: Vec<RankedPlan>,
+    // plans to the best states of the last beam level, at most
+    // Config::warm_start of them, for next turn's beam to start from
+    survivors: Vec<Vec<Action>>,
+}
+
+impl SearchResult {
+    // `reversed_path` runs from the target state back to the root
+    fn new(mut reversed_path: Vec<Action>, root_values: RootValues, stats: SearchStats) -> Self {
+        reversed_path.reverse();
+        Self {
+            plan: reversed_path,
+            root_values,
+            stats,
+            alternatives: Vec::new(),
+            survivors: Vec::new(),
+        }
+    }
+}
+
+struct Node {
+    state: State,
+    // first action on the path from the search root, None for the root itself
+    root: Option<Action>,
+    depth: usize,
+    last_cast: Option<LastCast>,
+    // what the opponent has left to snipe orders with, SearchMode::Paranoid only
+    rival: Option<Vec4>,
+}
+
+impl Node {
+    fn new(state: State, root: Action, depth: usize, last_cast: Option<LastCast>) -> Self {
+        Self {
+            state,
+            root: Some(root),
+            depth,
+            last_cast,
+            rival: None,
+        }
+    }
+
+    fn root(state: State) -> Self {
+        Self {
+            state,
+            root: None,
+            depth: 0,
+            last_cast: None,
+            rival: None,
+        }
+    }
+}
+
+// The cast that produced a node, and the inventory it was cast from.
+#[derive(Debug, Copy, Clone)]
+struct LastCast {
+    id: EngineId,
+    delta: Vec4,
+    before: Vec4,
+}
+
+impl LastCast {
+    // Casting `spell` now reaches the same state as casting it first and this
+    // cast second. When that order is also legal, only generate the pair with
+    // ascending ids.
+    fn makes_redundant(self, spell: &Spell) -> bool {
+        if spell.id >= self.id || !spell.can_be_afforded_by(self.before) {
+            return false;
+        }
+        let swapped = self.before + spell.delta + self.delta;
+        swapped.is_non_neg() && swapped.total() <= MAX_INVENTORY
+    }
+}
+
+// Macro actions: common multi-turn sequences searched as one branch. For
+// now the one every engine leans on, a spell cast, a rest, and the same
+// spell again, e.g. a tier-0 producer run twice or two tier-1 to tier-2
+// conversions in a row.
+fn macro_chains(state: &State) -> Vec<[Action; 3]> {
+    let inventory = state.me.inventory;
+    state
+        .me
+        .spells
+        .iter()
+        .filter(|s| s.is_castable && s.can_be_afforded_by(inventory))
+        .filter(|s| s.can_be_afforded_by(inventory + s.delta))
+        .map(|s| [Action::Cast(s.id), Action::Rest, Action::Cast(s.id)])
+        .collect()
+}
+
+// every child of `state`, less casts that only reorder `last_cast`
+fn expand(
+    state: &State,
+    last_cast: Option<LastCast>,
+    cast_cache: &mut CastCache,
+    stats: &mut SearchStats,
+) -> Vec<(Action, State, Option<LastCast>)> {
+    let mut children = Vec::new();
+    for action in state.get_possible_actions_cached(cast_cache) {
+        let mut next_cast = None;
+        if let Action::Cast(id) = action {
+            let spell = &state.me.spells[state.spell_slot(id).unwrap().0];
+            if last_cast.is_some_and(|last| last.makes_redundant(spell)) {
+                stats.pruned += 1;
+                continue;
+            }
+            next_cast = Some(LastCast {
+                id,
+                delta: spell.delta,
+                before: state.me.inventory,
+            });
+        }
+
+        let mut next = state.clone();
+        next.apply_unchecked(action);
+        children.push((action, next, next_cast));
+    }
+    children
+}
+
+// A frontier entry for Bot::best_first, ordered by value alone.
+struct Ranked {
+    value: f64,
+    node: Node,
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.value.total_cmp(&other.value).is_eq()
+    }
+}
+
+impl Eq for Ranked {}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.value.total_cmp(&other.value)
+    }
+}
+
+// Bookkeeping shared by the tree searches: how each state was first reached,
+// the fallback target when no brew turns up, and what to report.
+struct SearchTree {
+    initial_state: State,
+    visited: HashSet<State>,
+    predecessor: HashMap<State, State>,
+    // first action into each state, with the depth it was reached at
+    pred_action: HashMap<State, (Action, usize)>,
+    // other parents that reach a state at that same depth
+    alternates: HashMap<State, Vec<(State, Action)>>,
+    // states first reached along a macro chain, ahead of their depth in a
+    // breadth-first search; a plain link at no greater depth replaces theirs
+    macro_linked: HashSet<State>,
+    // scores the opening move when choosing between equally short paths
+    weights: Weights,
+    root_values: RootValues,
+    cast_cache: CastCache,
+    stats: SearchStats,
+    // no brew reachable in time: head for the most valuable state seen instead
+    best_state: State,
+    best_value: f64,
+    stale_goal: Option<State>,
+    // Player::brew_count a goal state must reach before its final brew, the
+    // root's plus Config::brew_target - 1; `partial_goal` is the first state
+    // short of that
+    brews_before_goal: u32,
+    partial_goal: Option<State>,
+    // best evaluated state under each first action, when top_plans is on
+    leaves: Vec<(Action, f64, State)>,
+    top_plans: usize,
+    // with Config::dominance
+    dominance: Option<DominanceIndex>,
+    // with Config::history
+    history: Option<History>,
+}
+
+impl SearchTree {
+    fn new(state: &State, value: f64, config: &Config) -> Self {
+        let mut visited = HashSet::new();
+        visited.insert(state.clone());
+        let mut pred_action = HashMap::new();
+        pred_action.insert(state.clone(), (Action::Wait, 0));
+        let mut tree = Self {
+            initial_state: state.clone(),
+            visited,
+            predecessor: HashMap::new(),
+            pred_action,
+            alternates: HashMap::new(),
+            macro_linked: HashSet::new(),
+            weights: config.weights,
+            root_values: RootValues::default(),
+            cast_cache: CastCache::new(),
+            stats: SearchStats::default(),
+            best_state: state.clone(),
+            best_value: value,
+            stale_goal: None,
+            brews_before_goal: state.me.brew_count + config.brew_target - 1,
+            partial_goal: None,
+            leaves: Vec::new(),
+            top_plans: config.top_plans,
+            dominance: None,
+            history: None,
+        };
+        if config.dominance {
+            tree.dominance = Some(DominanceIndex::new());
+            tree.index(state, 0);
+        }
+        tree
+    }
+
+    fn count(&mut self, node: &Node) {
+        self.stats.nodes += 1;
+        self.stats.max_depth = self.stats.max_depth.max(node.depth);
+    }
+
+    // true when `state` can brew a fresh order; a stale one is only
+    // remembered, in case nothing fresher turns up
+    fn is_goal(&mut self, state: &State) -> bool {
+        match state.find_brewable_order() {
+            Some(order) if state.is_stale_target(order) => {
+                if self.stale_goal.is_none() {
+                    self.stale_goal = Some(state.clone());
+                }
+                false
+            }
+            Some(_) if state.me.brew_count < self.brews_before_goal => {
+                if self.partial_goal.is_none() {
+                    self.partial_goal = Some(state.clone());
+                }
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    fn consider(&mut self, node: &Node, value: f64) {
+        if value > self.best_value {
+            self.best_value = value;
+            self.best_state = node.state.clone();
+        }
+        if let (true, Some(root)) = (self.top_plans > 0, node.root) {
+            match self
+                .leaves
+                .iter_mut()
+                .find(|(action, _, _)| *action == root)
+            {
+                Some(leaf) if leaf.1 < value => {
+                    *leaf = (root, value, node.state.clone());
+                }
+                Some(_) => {}
+                None => self.leaves.push((root, value, node.state.clone())),
+            }
+        }
+    }
+
+    fn expand(&mut self, node: &Node) -> Vec<(Action, State, Option<LastCast>)> {
+        let mut children = expand(
+            &node.state,
+            node.last_cast,
+            &mut self.cast_cache,
+            &mut self.stats,
+        );
+        if let Some(history) = self.history.as_ref() {
+            history.order(node.depth, &mut children);
+        }
+        children
+    }
+
+    // link, unless some state already linked dominates `next`
+    fn admit(&mut self, parent: &State, action: Action, next: &State) -> bool {
+        let depth = self.pred_action.get(parent).map_or(0, |&(_, d)| d) + 1;
+        if self.is_dominated(next, depth) {
+            self.stats.dominated += 1;
+            return false;
+        }
+        self.link(parent, action, next)
+    }
+
+    // records how `next` was first reached; false if it had been seen before
+    fn link(&mut self, parent: &State, action: Action, next: &State) -> bool {
+        let depth = self.pred_action.get(parent).map_or(0, |&(_, d)| d) + 1;
+        if !self.visited.insert(next.clone()) {
+            let linked_at = self.pred_action.get(next).map(|&(_, d)| d);
+            if self.macro_linked.contains(next) && linked_at.is_some_and(|d| d >= depth) {
+                self.macro_linked.remove(next);
+                self.alternates.remove(next);
+                self.pred_action.insert(next.clone(), (action, depth));
+                self.predecessor.insert(next.clone(), parent.clone());
+                self.index(next, depth);
+                return true;
+            }
+            if linked_at == Some(depth) {
+                self.alternates
+                    .entry(next.clone())
+                    .or_default()
+                    .push((parent.clone(), action));
+            }
+            return false;
+        }
+        self.pred_action.insert(next.clone(), (action, depth));
+        self.predecessor.insert(next.clone(), parent.clone());
+        self.stats.note_visited(self.visited.len());
+        self.index(next, depth);
+        true
+    }
+
+    // like link, for a state along a macro chain. It stays out of the
+    // dominance index and gets no alternate parents: both assume states
+    // arrive shallowest first, which macro chains don't.
+    fn link_macro(&mut self, parent: &State, action: Action, next: &State) -> bool {
+        if !self.visited.insert(next.clone()) {
+            return false;
+        }
+        let depth = self.pred_action.get(parent).map_or(0, |&(_, d)| d) + 1;
+        self.pred_action.insert(next.clone(), (action, depth));
+        self.predecessor.insert(next.clone(), parent.clone());
+        self.macro_linked.insert(next.clone());
+        self.stats.note_visited(self.visited.len());
+        true
+    }
+
+    fn dominance_key(state: &State) -> (Vec<EngineId>, Vec<EngineId>, usize) {
+        let orders = state.orders.iter().map(|o| o.id).collect();
+        let spells = state.me.spells.iter().map(|s| s.id).collect();
+        (orders, spells, state.tome.spells.len())
+    }
+
+    fn index(&mut self, state: &State, depth: usize) {
+        if let Some(dominance) = self.dominance.as_mut() {
+            let entry = (
+                state.me.inventory,
+                state.me.score,
+                state.castable_mask(),
+                depth,
+            );
+            dominance
+                .entry(Self::dominance_key(state))
+                .or_default()
+                .push(entry);
+        }
+    }
+
+    // some state linked at `depth` or shallower has the inventory, at least
+    // the score and the castable spells of `state`, so `state` can't lead
+    // anywhere better. A larger inventory doesn't dominate: it has less room
+    // under the cap and can lose casts the smaller one still has, and equal
+    // totals with at least as much of every tier means the same inventory.
+    // `state` itself doesn't count, so link still sees it arrive again by
+    // another path and can keep that as an alternate.
+    fn is_dominated(&self, state: &State, depth: usize) -> bool {
+        let dominance = match self.dominance.as_ref() {
+            Some(dominance) => dominance,
+            None => return false,
+        };
+        let castable = state.castable_mask();
+        dominance
+            .get(&Self::dominance_key(state))
+            .is_some_and(|entries| {
+                entries.iter().any(|&(inventory, score, mask, at)| {
+                    at <= depth
+                        && inventory == state.me.inventory
+                        && score >= state.me.score
+                        && mask & castable == castable
+                        && (score, mask) != (state.me.score, castable)
+                })
+            })
+    }
+
+    // the plan toward `goal`, or toward the fallback target without one
+    fn take_result(&mut self, goal: Option<&State>) -> SearchResult {
+        let target = goal
+            .or(self.partial_goal.as_ref())
+            .or(self.stale_goal.as_ref())
+            .unwrap_or(&self.best_state);
+        let path = self.reconstruct_path(target);
+        let alternatives = self.rank_plans();
+        let root_values = std::mem::take(&mut self.root_values);
+        let mut result = SearchResult::new(path, root_values, self.stats);
+        result.alternatives = alternatives;
+        result
+    }
+
+    // the nodes of `frontier` below `into`'s root, which must be one move
+    // from this tree's, relinked into `into` along the paths that found them
+    fn reroot(&self, frontier: LinkedList<Node>, into: &mut SearchTree) -> LinkedList<Node> {
+        let mut nodes = LinkedList::new();
+        let first = match self.pred_action.get(&into.initial_state) {
+            Some(&(action, 1)) => action,
+            _ => return nodes,
+        };
+        'nodes: for node in frontier.into_iter().filter(|node| node.root == Some(first)) {
+            // up to the root, or to where an earlier node's path already is
+            let mut links = Vec::new();
+            let mut child = &node.state;
+            while !into.visited.contains(child) {
+                // a macro relink can move a path off the new root
+                let parent = match self.predecessor.get(child) {
+                    Some(parent) => parent,
+                    None => continue 'nodes,
+                };
+                links.push((parent, self.pred_action[child].0, child));
+                child = parent;
+            }
+            for &(parent, action, next) in links.iter().rev() {
+                into.link(parent, action, next);
+            }
+            let (mut opening, depth) = into.pred_action[&node.state];
+            let mut state = &node.state;
+            while let Some(parent) = into.predecessor.get(state) {
+                opening = into.pred_action[state].0;
+                state = parent;
+            }
+            nodes.push_back(Node::new(node.state, opening, depth, None));
+        }
+        nodes
+    }
+
+    // the top_plans best leaves, one per first action, with their paths
+    fn rank_plans(&self) -> Vec<RankedPlan> {
+        let mut leaves: Vec<&(Action, f64, State)> = self.leaves.iter().collect();
+        leaves.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        leaves
+            .into_iter()
+            .take(self.top_plans)
+            .map(|(_, value, leaf)| {
+                let mut plan = self.reconstruct_path(leaf);
+                plan.reverse();
+                RankedPlan {
+                    plan,
+                    value: *value,
+                }
+            })
+            .collect()
+    }
+
+    // root-first plans to each of `targets`
+    fn plans_to(&self, targets: &[State]) -> Vec<Vec<Action>> {
+        targets
+            .iter()
+            .map(|target| {
+                let mut plan = self.reconstruct_path(target);
+                plan.reverse();
+                plan
+            })
+            .collect()
+    }
+
+    // Among the shortest paths to `target`, follows the one whose first move
+    // leads to the best evaluated state, so a pointless opening Rest loses to
+    // an equally short plan that casts first. Ties keep the first-found path.
+    fn reconstruct_path(&self, target: &State) -> Vec<Action> {
+        let mut choice = HashMap::new();
+        if *target != self.initial_state {
+            self.best_opening(target, &mut choice);
+        }
+        let mut path = Vec::<Action>::new();
+        let mut curr_state = target;
+        while *curr_state != self.initial_state {
+            let (_, last_state, action) = choice.get(curr_state).expect("prev state not found");
+            path.push(*action);
+            curr_state = last_state;
+        }
+        path
+    }
+
+    // value of the best opening move on a shortest path to `state`, memoizing
+    // which parent that path goes through
+    fn best_opening(
+        &self,
+        state: &State,
+        choice: &mut HashMap<State, (f64, State, Action)>,
+    ) -> f64 {
+        if let Some(&(value, ..)) = choice.get(state) {
+            return value;
+        }
+        let first = (
+            self.predecessor
+                .get(state)
+                .expect("prev state not found")
+                .clone(),
+            self.pred_action
+                .get(state)
+                .expect("pred action not found")
+                .0,
+        );
+        let others = self.alternates.get(state).into_iter().flatten().cloned();
+        let mut best: Option<(f64, State, Action)> = None;
+        for (parent, action) in std::iter::once(first).chain(others) {
+            let value = if parent == self.initial_state {
+                state.evaluate(&self.weights)
+            } else {
+                self.best_opening(&parent, choice)
+            };
+            if best.as_ref().is_none_or(|b| value > b.0) {
+                best = Some((value, parent, action));
+            }
+        }
+        let best = best.expect("no path to state");
+        let value = best.0;
+        choice.insert(state.clone(), best);
+        value
+    }
+}
+
+// One depth-limited pass of Bot::iddfs. The path so far lives on a stack
+// instead of in predecessor maps, and `seen` skips states already searched
+// with at least as many moves left.
+struct DepthFirst<'a> {
+    bot: &'a Bot,
+    start_instant: &'a Instant,
+    budget: Duration,
+    limit: usize,
+    cast_cache: CastCache,
+    stats: SearchStats,
+    seen: HashMap<State, usize>,
+    path: Vec<Action>,
+    root_values: RootValues,
+    best_value: f64,
+    best_plan: Vec<Action>,
+    stale_goal: Option<Vec<Action>>,
+    goal: Option<Vec<Action>>,
+    history: Option<History>,
+    // some node sat at the limit, so a deeper pass could find more
+    cut: bool,
+    timed_out: bool,
+}
+
+impl<'a> DepthFirst<'a> {
+    fn new(bot: &'a Bot, start_instant: &'a Instant, budget: Duration, limit: usize) -> Self {
+        Self {
+            bot,
+            start_instant,
+            budget,
+            limit,
+            cast_cache: CastCache::new(),
+            stats: SearchStats::default(),
+            seen: HashMap::new(),
+            path: Vec::new(),
+            root_values: RootValues::default(),
+            best_value: f64::MIN,
+            best_plan: Vec::new(),
+            stale_goal: None,
+            goal: None,
+            history: None,
+            cut: false,
+            timed_out: false,
+        }
+    }
+
+    fn visit(&mut self, state: &State, last_cast: Option<LastCast>) {
+        let depth = self.path.len();
+        if self
+            .bot
+            .out_of_budget(self.start_instant, self.budget, &self.stats)
+        {
+            self.timed_out = true;
+            return;
+        }
+        self.stats.nodes += 1;
+        self.stats.max_depth = self.stats.max_depth.max(depth);
+
+        if depth > 0 {
+            if let Some(order) = state.find_brewable_order() {
+                if !state.is_stale_target(order) {
+                    self.goal = Some(self.path.clone());
+                    return;
+                }
+                if self.stale_goal.is_none() {
+                    self.stale_goal = Some(self.path.clone());
+                }
+            }
+        }
+
+        let moves_left = self.limit - depth;
+        if self.seen.get(state).is_some_and(|&seen| seen >= moves_left) {
+            return;
+        }
+        self.seen.insert(state.clone(), moves_left);
+        self.stats.note_visited(self.seen.len());
+
+        let weights = &self.bot.config.weights;
+        let value = self.bot.risk_adjusted(state.evaluate(weights), depth);
+        if value > self.best_value {
+            self.best_value = value;
+            self.best_plan = self.path.clone();
+        }
+        if let Some(&root) = self.path.first() {
+            self.root_values.record(root, value);
+        }
+
+        if moves_left == 0 {
+            self.cut = true;
+            return;
+        }
+
+        let mut children = expand(state, last_cast, &mut self.cast_cache, &mut self.stats);
+        if let Some(history) = self.history.as_ref() {
+            history.order(depth, &mut children);
+        }
+        for (action, next, next_cast) in children {
+            self.path.push(action);
+            self.visit(&next, next_cast);
+            self.path.pop();
+            if self.goal.is_some() || self.timed_out {
+                return;
+            }
+        }
+    }
+}
+
+// Depth-first branch and bound for Bot::dfbnb. Unlike the other searches it
+// keeps going past a brew, maximising the score at the horizon, and drops
+// any subtree whose bound::score_upper_bound can't beat the best plan so far.
+struct BranchAndBound<'a> {
+    bot: &'a Bot,
+    start_instant: &'a Instant,
+    budget: Duration,
+    horizon: usize,
+    cast_cache: CastCache,
+    stats: SearchStats,
+    seen: HashMap<State, usize>,
+    path: Vec<Action>,
+    root_values: RootValues,
+    // score first, evaluation to break ties
+    best_score: i32,
+    best_value: f64,
+    best_plan: Vec<Action>,
+    timed_out: bool,
+}
+
+impl<'a> BranchAndBound<'a> {
+    fn new(bot: &'a Bot, start_instant: &'a Instant, budget: Duration, horizon: usize) -> Self {
+        Self {
+            bot,
+            start_instant,
+            budget,
+            horizon,
+            cast_cache: CastCache::new(),
+            stats: SearchStats::default(),
+            seen: HashMap::new(),
+            path: Vec::new(),
+            root_values: RootValues::default(),
+            best_score: i32::MIN,
+            best_value: f64::MIN,
+            best_plan: Vec::new(),
+            timed_out: false,
+        }
+    }
+
+    fn visit(&mut self, state: &State, last_cast: Option<LastCast>) {
+        let depth = self.path.len();
+        if self
+            .bot
+            .out_of_budget(self.start_instant, self.budget, &self.stats)
+        {
+            self.timed_out = true;
+            return;
+        }
+        self.stats.nodes += 1;
+        self.stats.max_depth = self.stats.max_depth.max(depth);
+
+        self.record(state, depth);
+
+        let moves_left = self.horizon - depth;
+        if moves_left == 0 {
+            if self.bot.config.quiescence {
+                self.extend_brew(state, depth);
+            }
+            return;
+        }
+        // the quiescence brew is one more move the bound has to allow for
+        let bound_moves = moves_left + self.bot.config.quiescence as usize;
+        if bound::score_upper_bound(state, bound_moves) < self.best_score {
+            return;
+        }
+        if self.seen.get(state).is_some_and(|&seen| seen >= moves_left) {
+            return;
+        }
+        self.seen.insert(state.clone(), moves_left);
+        self.stats.note_visited(self.seen.len());
+
+        for (action, next, next_cast) in
+            expand(state, last_cast, &mut self.cast_cache, &mut self.stats)
+        {
+            self.path.push(action);
+            self.visit(&next, next_cast);
+            self.path.pop();
+            if self.timed_out {
+                return;
+            }
+        }
+    }
+
+    fn record(&mut self, state: &State, depth: usize) {
+        let weights = &self.bot.config.weights;
+        let value = self.bot.risk_adjusted(state.evaluate(weights), depth);
+        let score = state.me.score;
+        // standing still is no plan, so the root itself never counts
+        if depth > 0 && (score, value) > (self.best_score, self.best_value) {
+            self.best_score = score;
+            self.best_value = value;
+            self.best_plan = self.path.clone();
+        }
+        if let Some(&root) = self.path.first() {
+            self.root_values.record(root, value);
+        }
+    }
+
+    // Quiescence at the horizon: a line cut off one brew short of a potion
+    // is scored with that brew played, instead of as if the potion were out
+    // of reach. Only the dearest brew, and nothing past it.
+    fn extend_brew(&mut self, state: &State, depth: usize) {
+        if let Some(&brew) = state.brews_by_price().first() {
+            let mut next = state.clone();
+            next.apply_unchecked(brew);
+            self.stats.nodes += 1;
+            self.path.push(brew);
+            self.record(&next, depth + 1);
+            self.path.pop();
+        }
+    }
+}
+
+// Optimistic limits on what a state can still earn, for pruning searches.
+mod bound {
+    use super::{Order, State, TIER_VALUES};
+
+    // value of what `delta` adds to the inventory, ignoring what it consumes
+    fn gross_gain(delta: [i32; 4]) -> f64 {
+        delta
+            .iter()
+            .zip(TIER_VALUES.iter())
+            .map(|(&count, &value)| count.max(0) as f64 * value)
+            .sum()
+    }
+
+    // fewest turns until `order` is brewed, the brew included, or None when
+    // some missing tier has no spell producing it. No cast adds more of a
+    // tier than the spell producing most of it, nor more to the inventory
+    // than the best spell's gross gain, so either count of casts is a floor.
+    pub fn min_turns(state: &State, order: &Order) -> Option<usize> {
+        let left = (state.me.inventory + order.delta).to_array();
+        let shortfall = gross_gain([-left[0], -left[1], -left[2], -left[3]]);
+        if shortfall == 0.0 {
+            return Some(1);
+        }
+
+        let mut max_output = [0; 4];
+        let mut best_gain = 0.0;
+        for spell in state.me.spells.iter() {
+            let delta = spell.delta.to_array();
+            for tier in 0..4 {
+                max_output[tier] = max_output[tier].max(delta[tier]);
+            }
+            best_gain = f64::max(best_gain, gross_gain(delta));
+        }
+
+        if best_gain <= 0.0 {
+            return None;
+        }
+        let mut casts = (shortfall / best_gain).ceil() as usize;
+        for tier in 0..4 {
+            let deficit = -left[tier];
+            if deficit <= 0 {
+                continue;
+            }
+            if max_output[tier] <= 0 {
+                return None;
+            }
+            casts = casts.max(((deficit + max_output[tier] - 1) / max_output[tier]) as usize);
+        }
+        Some(1 + casts)
+    }
+
+    // the score now plus the dearest orders that could each be brewed within
+    // `moves`, at most one per move
+    pub fn score_upper_bound(state: &State, moves: usize) -> i32 {
+        let mut prices: Vec<i32> = state
+            .orders
+            .iter()
+            .filter(|o| min_turns(state, o).is_some_and(|turns| turns <= moves))
+            .map(|o| o.price)
+            .collect();
+        prices.sort_unstable_by(|a, b| b.cmp(a));
+        state.me.score + prices.iter().take(moves).sum::<i32>()
+    }
+}
+
+// A cheap playout policy for the Monte Carlo searches: a weighted random
+// pick that favours brews, then casts that add value, then resting, so
+// playouts spend their moves on progress instead of Rest/Cast loops.
+mod rollout {
+    use super::{inventory_value, Action, Rng, State, Weights};
+
+    // relative pick weights; a cast that loses inventory value weighs 1.0
+    #[derive(Debug, Copy, Clone)]
+    pub struct Bias {
+        pub brew: f64,
+        pub cast: f64,
+        pub rest: f64,
+    }
+
+    impl Default for Bias {
+        fn default() -> Self {
+            Self {
+                brew: 50.0,
+                cast: 4.0,
+                rest: 0.5,
+            }
+        }
+    }
+
+    fn weight(state: &State, action: Action, bias: &Bias) -> f64 {
+        match action {
+            Action::Brew(_) => bias.brew,
+            Action::Cast(id) => {
+                let productive = state
+                    .spell_slot(id)
+                    .map(|slot| state.me.spells[slot.0].delta)
+                    .is_some_and(|delta| inventory_value(delta) > 0.0);
+                if productive {
+                    bias.cast
+                } else {
+                    1.0
+                }
+            }
+            Action::Rest => bias.rest,
+            Action::Learn(_) | Action::Wait => 1.0,
+        }
+    }
+
+    pub fn choose(state: &State, bias: &Bias, rng: &mut Rng) -> Option<Action> {
+        let actions = state.get_possible_actions();
+        let weights: Vec<f64> = actions.iter().map(|&a| weight(state, a, bias)).collect();
+        let mut pick = rng.next_f64() * weights.iter().sum::<f64>();
+        for (&action, &weight) in actions.iter().zip(weights.iter()) {
+            if pick < weight {
+                return Some(action);
+            }
+            pick -= weight;
+        }
+        actions.last().copied()
+    }
+
+    // value after `depth` moves of the policy, like State::rollout_value
+    pub fn playout(
+        state: &State,
+        weights: &Weights,
+        depth: usize,
+        bias: &Bias,
+        rng: &mut Rng,
+    ) -> f64 {
+        let mut state = state.clone();
+        for _ in 0..depth {
+            match choose(&state, bias, rng) {
+                Some(action) => state.apply_unchecked(action),
+                None => break,
+            }
+        }
+        state.value_with_brew(weights)
+    }
+}
+
+// Searches other than the level-by-level ones in Bot.
+mod search {
+    // Monte Carlo tree search with UCT selection. Each iteration walks down
+    // the most promising branch, adds one child and scores it with a short
+    // greedy rollout, so multi-brew lines get credit the single-state BFS
+    // evaluation misses.
+    pub mod mcts {
+        use super::super::{Action, RootValues, SearchResult, SearchStats, State};
+
+        // iterations, playout included, per bfs node expanded in the same time
+        const ITERATIONS_PER_NODE: usize = 2;
+
+        // tuning knobs, set from Config's mcts.* keys
+        #[derive(Debug, Copy, Clone)]
+        pub struct Params {
+            // UCT's c: how much a rarely visited child is worth trying
+            pub exploration: f64,
+            // moves each playout runs past the new leaf
+            pub rollout_depth: usize,
+            // progressive bias: a child's static value, scaled like the
+            // rollout rewards, weighs this much divided by its visits + 1;
+            // 0 leaves selection to UCT alone and skips evaluating priors
+            pub prior_weight: f64,
+            // progressive widening: past this depth a node only gets
+            // ceil(widen_base * visits^widen_exponent) children, best actions
+            // first by get_possible_actions' order; usize::MAX never widens
+            pub widen_depth: usize,
+            pub widen_base: f64,
+            pub widen_exponent: f64,
+        }
+
+        impl Default for Params {
+            fn default() -> Self {
+                Self {
+                    exploration: 1.4,
+                    rollout_depth: 6,
+                    prior_weight: 0.0,
+                    widen_depth: usize::MAX,
+                    widen_base: 1.0,
+                    widen_exponent: 0.5,
+                }
+            }
+        }
+
+        struct TreeNode {
+            state: State,
+            // the action leading here, None for the root
+            action: Option<Action>,
+            parent: Option<usize>,
+            children: Vec<usize>,
+            untried: Vec<Action>,
+            depth: usize,
+            visits: u32,
+            total: f64,
+            prior: f64,
+        }
+
+        impl TreeNode {
+            fn new(
+                state: State,
+                action: Option<Action>,
+                parent: Option<usize>,
+                depth: usize,
+                prior: f64,
+                params: &Params,
+            ) -> Self {
+                let mut untried = state.get_possible_actions();
+                if depth > params.widen_depth {
+                    // popped from the back, so the best come out first
+                    untried.reverse();
+                }
+                Self {
+                    state,
+                    action,
+                    parent,
+                    children: Vec::new(),
+                    untried,
+                    depth,
+                    visits: 0,
+                    total: 0.0,
+                    prior,
+                }
+            }
+
+            fn mean(&self) -> f64 {
+                self.total / self.visits.max(1) as f64
+            }
+
+            fn can_expand(&self, params: &Params) -> bool {
+                if self.untried.is_empty() {
+                    return false;
+                }
+                if self.depth <= params.widen_depth {
+                    return true;
+                }
+                let visits = self.visits.max(1) as f64;
+                let allowed = (params.widen_base * visits.powf(params.widen_exponent)).ceil();
+                (self.children.len() as f64) < allowed
+            }
+        }
+
+        // runs until `out_of_budget` says stop; never expands past `max_depth`.
+        // `playout` scores a new leaf by playing on for the given depth,
+        // `prior` statically scores a new child for the progressive bias.
+        pub fn search(
+            state: &State,
+            max_depth: usize,
+            params: &Params,
+            prior: impl Fn(&State) -> f64,
+            mut playout: impl FnMut(&State, usize) -> f64,
+            out_of_budget: impl Fn(&SearchStats) -> bool,
+        ) -> SearchResult {
+            let mut nodes = vec![TreeNode::new(state.clone(), None, None, 0, 0.0, params)];
+            let mut stats = SearchStats::default();
+            // rollout values seen so far, to scale rewards into [0, 1] for UCT
+            let mut low = f64::MAX;
+            let mut high = f64::MIN;
+            let mut iterations = 0;
+
+            while !out_of_budget(&stats) {
+                iterations += 1;
+                stats.nodes = iterations / ITERATIONS_PER_NODE;
+
+                let mut current = 0;
+                while !nodes[current].can_expand(params) && !nodes[current].children.is_empty() {
+                    current = select(&nodes, current, params, low, high);
+                }
+
+                if nodes[current].depth < max_depth && nodes[current].can_expand(params) {
+                    if let Some(action) = nodes[current].untried.pop() {
+                        let mut next = nodes[current].state.clone();
+                        next.apply_unchecked(action);
+                        let depth = nodes[current].depth + 1;
+                        let next_prior = if params.prior_weight > 0.0 {
+                            prior(&next)
+                        } else {
+                            0.0
+                        };
+                        let node = TreeNode::new(
+                            next,
+                            Some(action),
+                            Some(current),
+                            depth,
+                            next_prior,
+                            params,
+                        );
+                        nodes.push(node);
+                        stats.note_arena(nodes.len());
+                        let child = nodes.len() - 1;
+                        nodes[current].children.push(child);
+                        current = child;
+                    }
+                }
+                stats.max_depth = stats.max_depth.max(nodes[current].depth);
+
+                let value = playout(&nodes[current].state, params.rollout_depth);
+                low = low.min(value);
+                high = high.max(value);
+
+                let mut walk = Some(current);
+                while let Some(index) = walk {
+                    nodes[index].visits += 1;
+                    nodes[index].total += value;
+                    walk = nodes[index].parent;
+                }
+
+                if nodes[0].untried.is_empty() && nodes[0].children.is_empty() {
+                    // nothing legal to do from the root
+                    break;
+                }
+            }
+
+            let mut root_values = RootValues::default();
+            for &child in nodes[0].children.iter() {
+                root_values.record(nodes[child].action.unwrap(), nodes[child].mean());
+            }
+
+            // follow the most visited child down while it has been tried more than once
+            let mut plan = Vec::new();
+            let mut current = 0;
+            while let Some(&child) = nodes[current]
+                .children
+                .iter()
+                .max_by_key(|&&child| nodes[child].visits)
+            {
+                if current != 0 && nodes[child].visits < 2 {
+                    break;
+                }
+                plan.push(nodes[child].action.unwrap());
+                current = child;
+            }
+            plan.reverse();
+            SearchResult::new(plan, root_values, stats)
+        }
+
+        fn select(
+            nodes: &[TreeNode],
+            parent: usize,
+            params: &Params,
+            low: f64,
+            high: f64,
+        ) -> usize {
+            let range = (high - low).max(f64::EPSILON);
+            let log_visits = (nodes[parent].visits as f64).ln();
+            let uct = |child: usize| {
+                let node = &nodes[child];
+                let exploit = (node.mean() - low) / range;
+                let explore = params.exploration * (log_visits / node.visits as f64).sqrt();
+                let bias = params.prior_weight * (node.prior - low) / range;
+                exploit + explore + bias / (node.visits + 1) as f64
+            };
+            *nodes[parent]
+                .children
+                .iter()
+                .max_by(|&&a, &&b| uct(a).partial_cmp(&uct(b)).unwrap())
+                .unwrap()
+        }
+    }
+
+    // A* over casts and rests toward one order, counting turns, with
+    // bound::min_turns as the heuristic: it never overestimates the turns left.
+    pub mod astar {
+        use super::super::{bound, Action, Order, SearchStats, State};
+        use std::cmp::Reverse;
+        use std::collections::{BinaryHeap, HashSet};
+
+        // turns still needed before `order` can be brewed, the brew excluded
+        fn heuristic(state: &State, order: Order) -> Option<usize> {
+            bound::min_turns(state, &order).map(|turns| turns - 1)
+        }
+
+        // the shortest plan ending in brewing `order`, if one fits in `max_turns`
+        pub fn plan_to_order(
+            state: &State,
+            order: Order,
+            max_turns: usize,
+            stats: &mut SearchStats,
+            out_of_budget: impl Fn(&SearchStats) -> bool,
+        ) -> Option<Vec<Action>> {
+            // (state, parent, action from the parent, turns so far)
+            let mut nodes: Vec<(State, Option<usize>, Option<Action>, usize)> =
+                vec![(state.clone(), None, None, 0)];
+            let mut frontier = BinaryHeap::new();
+            let mut closed = HashSet::new();
+            let h = heuristic(state, order)?;
+            frontier.push(Reverse((h, 0)));
+
+            while let Some(Reverse((_, index))) = frontier.pop() {
+                if out_of_budget(stats) {
+                    return None;
+                }
+                let (current, _, _, turns) = &nodes[index];
+                let turns = *turns;
+                if !closed.insert((current.me.inventory, current.castable_mask())) {
+                    continue;
+                }
+                stats.note_visited(closed.len());
+                stats.nodes += 1;
+                stats.max_depth = stats.max_depth.max(turns);
+
+                if order.can_be_fulfilled_by(current.me.inventory) {
+                    let mut plan = vec![Action::Brew(order.id)];
+                    let mut walk = index;
+                    while let (_, Some(parent), Some(action), _) = &nodes[walk] {
+                        plan.push(*action);
+                        walk = *parent;
+                    }
+                    plan.reverse();
+                    return Some(plan);
+                }
+                if turns + 1 >= max_turns {
+                    continue;
+                }
+
+                let children: Vec<(State, Action)> = current
+                    .get_possible_actions()
+                    .into_iter()
+                    .filter(|a| matches!(a, Action::Cast(_) | Action::Rest))
+                    .map(|action| {
+                        let mut next = current.clone();
+                        next.apply_unchecked(action);
+                        (next, action)
+                    })
+                    .collect();
+                for (next, action) in children {
+                    if closed.contains(&(next.me.inventory, next.castable_mask())) {
+                        continue;
+                    }
+                    if let Some(h) = heuristic(&next, order) {
+                        nodes.push((next, Some(index), Some(action), turns + 1));
+                        frontier.push(Reverse((turns + 1 + h, nodes.len() - 1)));
+                        stats.note_arena(nodes.len());
+                        stats.note_frontier(frontier.len());
+                    }
+                }
+            }
+            None
+        }
+    }
+
+    // Uniform-cost search over casts and rests, one turn per edge, settling
+    // the fewest turns to every visible order in a single pass.
+    pub mod dijkstra {
+        use super::super::{Action, EngineId, SearchStats, State};
+        use std::cmp::Reverse;
+        use std::collections::{BinaryHeap, HashMap, HashSet};
+
+        // turns until each order can be brewed, the brew included; orders
+        // not reached within `max_turns` are left out
+        pub fn turns_to_orders(
+            state: &State,
+            max_turns: u32,
+            stats: &mut SearchStats,
+            out_of_budget: impl Fn(&SearchStats) -> bool,
+        ) -> HashMap<EngineId, u32> {
+            let mut turns_to = HashMap::new();
+            let mut states = vec![state.clone()];
+            let mut frontier = BinaryHeap::new();
+            let mut settled = HashSet::new();
+            frontier.push(Reverse((0, 0)));
+
+            while let Some(Reverse((turns, index))) = frontier.pop() {
+                if turns_to.len() == state.orders.len() || out_of_budget(stats) {
+                    break;
+                }
+                let current = &states[index];
+                if !settled.insert((current.me.inventory, current.castable_mask())) {
+                    continue;
+                }
+                stats.note_visited(settled.len());
+                stats.nodes += 1;
+                stats.max_depth = stats.max_depth.max(turns as usize);
+
+                for order in current.orders.iter() {
+                    if order.can_be_fulfilled_by(current.me.inventory) {
+                        turns_to.entry(order.id).or_insert(turns + 1);
+                    }
+                }
+                if turns + 2 > max_turns {
+                    continue;
+                }
+
+                let children: Vec<State> = current
+                    .get_possible_actions()
+                    .into_iter()
+                    .filter(|a| matches!(a, Action::Cast(_) | Action::Rest))
+                    .map(|action| {
+                        let mut next = current.clone();
+                        next.apply_unchecked(action);
+                        next
+                    })
+                    .collect();
+                for next in children {
+                    if !settled.contains(&(next.me.inventory, next.castable_mask())) {
+                        states.push(next);
+                        frontier.push(Reverse((turns + 1, states.len() - 1)));
+                        stats.note_arena(states.len());
+                        stats.note_frontier(frontier.len());
+                    }
+                }
+            }
+            turns_to
+        }
+    }
+
+    // Two-player search over alternating moves, mine first: the opponent
+    // gets to brew the orders I leave on the board, so plans that lose the
+    // race for an order score as they would in a real game.
+    pub mod minimax {
+        use super::super::{Action, Player, RootValues, SearchResult, SearchStats, State, Weights};
+
+        // Both players on one board. Only `state.me` ever moves: the
+        // opponent's move swaps it into that seat first, so State's rules
+        // serve both sides.
+        #[derive(Clone)]
+        pub struct Duel {
+            pub state: State,
+            pub enemy: Player,
+        }
+
+        impl Duel {
+            pub fn new(state: &State, enemy: &Player) -> Self {
+                Self {
+                    state: state.clone(),
+                    enemy: enemy.clone(),
+                }
+            }
+
+            fn swap_seats(&mut self) {
+                std::mem::swap(&mut self.state.me, &mut self.enemy);
+            }
+
+            // my evaluation less the opponent's
+            pub fn evaluate(&mut self, weights: &Weights) -> f64 {
+                let mine = self.state.evaluate(weights);
+                self.swap_seats();
+                let theirs = self.state.evaluate(weights);
+                self.swap_seats();
+                mine - theirs
+            }
+
+            pub fn actions(&mut self, mine: bool) -> Vec<Action> {
+                if mine {
+                    return self.state.get_possible_actions();
+                }
+                self.swap_seats();
+                let actions = self.state.get_possible_actions();
+                self.swap_seats();
+                actions
+            }
+
+            pub fn child(&self, action: Action, mine: bool) -> Self {
+                let mut child = self.clone();
+                if mine {
+                    child.state.apply_unchecked(action);
+                } else {
+                    child.swap_seats();
+                    child.state.apply_unchecked(action);
+                    child.swap_seats();
+                }
+                child
+            }
+        }
+
+        // my best first move, deepening one move per player at a time up to
+        // `max_plies`; a pass cut short by the budget is thrown away. With
+        // `pruning`, each pass is an alpha-beta search trying the previous
+        // pass's best moves first, and root values below the best are only
+        // upper bounds.
+        pub fn search(
+            state: &State,
+            enemy: &Player,
+            weights: &Weights,
+            max_plies: usize,
+            pruning: bool,
+            out_of_budget: impl Fn(&SearchStats) -> bool,
+        ) -> SearchResult {
+            let mut duel = Duel::new(state, enemy);
+            let mut stats = SearchStats::default();
+            let mut completed: Option<(Vec<Action>, RootValues)> = None;
+
+            for plies in 1..=max_plies {
+                let mut root_values = RootValues::default();
+                let mut best: Option<(f64, Action)> = None;
+                let mut actions = duel.actions(true);
+                if let (true, Some((_, previous))) = (pruning, completed.as_ref()) {
+                    let ranked = previous.sorted();
+                    actions.sort_by_key(|action| {
+                        ranked
+                            .iter()
+                            .position(|(a, _)| a == action)
+                            .unwrap_or(usize::MAX)
+                    });
+                }
+                for action in actions {
+                    let mut child = duel.child(action, true);
+                    let moves_left = plies * 2 - 1;
+                    let value = if pruning {
+                        let alpha = best.map_or(f64::MIN, |(value, _)| value);
+                        let window = (alpha, f64::MAX);
+                        alpha_beta(
+                            &mut child,
+                            weights,
+                            moves_left,
+                            false,
+                            1,
+                            window,
+                            &mut stats,
+                            &out_of_budget,
+                        )
+                    } else {
+                        minimax(
+                            &mut child,
+                            weights,
+                            moves_left,
+                            false,
+                            1,
+                            &mut stats,
+                            &out_of_budget,
+                        )
+                    };
+                    root_values.record(action, value);
+                    if best.is_none_or(|(best_value, _)| value > best_value) {
+                        best = Some((value, action));
+                    }
+                }
+                if completed.is_some() && out_of_budget(&stats) {
+                    break;
+                }
+                let plan = best.map(|(_, action)| vec![action]).unwrap_or_default();
+                completed = Some((plan, root_values));
+                if out_of_budget(&stats) {
+                    break;
+                }
+            }
+
+            let (plan, root_values) = completed.unwrap_or_default();
+            SearchResult::new(plan, root_values, stats)
+        }
+
+        fn minimax(
+            duel: &mut Duel,
+            weights: &Weights,
+            moves_left: usize,
+            mine: bool,
+            depth: usize,
+            stats: &mut SearchStats,
+            out_of_budget: &impl Fn(&SearchStats) -> bool,
+        ) -> f64 {
+            stats.nodes += 1;
+            stats.max_depth = stats.max_depth.max(depth);
+            if moves_left == 0 || out_of_budget(stats) {
+                return duel.evaluate(weights);
+            }
+            let actions = duel.actions(mine);
+            if actions.is_empty() {
+                return duel.evaluate(weights);
+            }
+
+            let values = actions.into_iter().map(|action| {
+                let mut child = duel.child(action, mine);
+                minimax(
+                    &mut child,
+                    weights,
+                    moves_left - 1,
+                    !mine,
+                    depth + 1,
+                    stats,
+                    out_of_budget,
+                )
+            });
+            if mine {
+                values.fold(f64::MIN, f64::max)
+            } else {
+                values.fold(f64::MAX, f64::min)
+            }
+        }
+
+        // minimax inside the (alpha, beta) window: I can already get alpha
+        // elsewhere and the opponent can already hold me to beta, so stop
+        // looking at a node's moves once they can't matter
+        #[allow(clippy::too_many_arguments)]
+        fn alpha_beta(
+            duel: &mut Duel,
+            weights: &Weights,
+            moves_left: usize,
+            mine: bool,
+            depth: usize,
+            (mut alpha, mut beta): (f64, f64),
+            stats: &mut SearchStats,
+            out_of_budget: &impl Fn(&SearchStats) -> bool,
+        ) -> f64 {
+            stats.nodes += 1;
+            stats.max_depth = stats.max_depth.max(depth);
+            if moves_left == 0 || out_of_budget(stats) {
+                return duel.evaluate(weights);
+            }
+            let actions = duel.actions(mine);
+            if actions.is_empty() {
+                return duel.evaluate(weights);
+            }
+
+            let mut best = if mine { f64::MIN } else { f64::MAX };
+            for action in actions {
+                let mut child = duel.child(action, mine);
+                let window = (alpha, beta);
+                let value = alpha_beta(
+                    &mut child,
+                    weights,
+                    moves_left - 1,
+                    !mine,
+                    depth + 1,
+                    window,
+                    stats,
+                    out_of_budget,
+                );
+                if mine {
+                    best = best.max(value);
+                    alpha = alpha.max(value);
+                } else {
+                    best = best.min(value);
+                    beta = beta.min(value);
+                }
+                if alpha >= beta {
+                    stats.cutoffs += 1;
+                    break;
+                }
+            }
+            best
+        }
+    }
+
+    // Simulated annealing over whole plans, seeded with another search's
+    // result. Each step swaps, deletes or inserts one action and replays the
+    // plan; a worse plan is still accepted with a chance that shrinks as the
+    // temperature cools, so the walk can leave a local optimum.
+    pub mod annealing {
+        use super::super::{Action, Rng, SearchResult, SearchStats, State, SCORE_WEIGHT};
+
+        // one rupee
+        const START_TEMPERATURE: f64 = SCORE_WEIGHT;
+        const MIN_TEMPERATURE: f64 = 0.01;
+        const COOLING: f64 = 0.995;
+        const MAX_PLAN_LEN: usize = 20;
+        const SEED: u64 = 0x2545_f491_4f6c_dd1d;
+        // steps that take about as long as expanding one bfs node, so the
+        // node caps measured on bfs also fit this search
+        const STEPS_PER_NODE: usize = 20;
+
+        // the state after `plan`, None if one of its actions is illegal by then
+        fn replay(state: &State, plan: &[Action]) -> Option<State> {
+            let mut state = state.clone();
+            for &action in plan {
+                state.try_apply(action).ok()?;
+            }
+            Some(state)
+        }
+
+        fn mutate(state: &State, plan: &[Action], rng: &mut Rng) -> Vec<Action> {
+            let mut plan = plan.to_vec();
+            match rng.below(3) {
+                0 if plan.len() >= 2 => {
+                    let (i, j) = (rng.below(plan.len()), rng.below(plan.len()));
+                    plan.swap(i, j);
+                }
+                1 if plan.len() >= 2 => {
+                    plan.remove(rng.below(plan.len()));
+                }
+                _ if plan.len() < MAX_PLAN_LEN => {
+                    let at = rng.below(plan.len() + 1);
+                    if let Some(before) = replay(state, &plan[..at]) {
+                        let actions = before.get_possible_actions();
+                        if !actions.is_empty() {
+                            plan.insert(at, actions[rng.below(actions.len())]);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            plan
+        }
+
+        // `value` scores the state a plan ends in, given the plan's length;
+        // runs until `out_of_budget` says stop. `rng_seed` varies the walk.
+        pub fn improve(
+            state: &State,
+            mut seed: SearchResult,
+            rng_seed: u64,
+            value: impl Fn(&State, usize) -> f64,
+            out_of_budget: impl Fn(&SearchStats) -> bool,
+        ) -> SearchResult {
+            let score = |plan: &[Action]| replay(state, plan).map(|end| value(&end, plan.len()));
+            let mut current = seed.plan.clone();
+            let mut current_value = match score(&current) {
+                Some(value) => value,
+                None => return seed,
+            };
+            let mut best_value = current_value;
+            let mut rng = Rng::new(SEED ^ rng_seed);
+            let mut stats = SearchStats::default();
+            let mut temperature = START_TEMPERATURE;
+            let mut steps = 0;
+
+            while !out_of_budget(&stats) {
+                steps += 1;
+                stats.nodes = steps / STEPS_PER_NODE;
+                let candidate = mutate(state, &current, &mut rng);
+                if let Some(candidate_value) = score(&candidate) {
+                    let delta = candidate_value - current_value;
+                    if delta >= 0.0 || rng.next_f64() < (delta / temperature).exp() {
+                        current = candidate;
+                        current_value = candidate_value;
+                        if current_value > best_value && !current.is_empty() {
+                            best_value = current_value;
+                            seed.plan = current.clone();
+                        }
+                    }
+                }
+                temperature = (temperature * COOLING).max(MIN_TEMPERATURE);
+            }
+
+            seed.stats.nodes += stats.nodes;
+            seed.stats.max_depth = seed.stats.max_depth.max(seed.plan.len());
+            seed
+        }
+    }
+
+    // Genetic algorithm over fixed-length action genomes. Genes that are
+    // illegal by the time they come up are repaired to a random legal action,
+    // so crossover and mutation never produce unplayable plans.
+    pub mod genetic {
+        use super::super::{Action, Rng, RootValues, SearchResult, SearchStats, State};
+
+        const POPULATION: usize = 32;
+        const GENOME_LEN: usize = 10;
+        // chance that a gene is replaced by a fresh random action
+        const MUTATION_RATE: f64 = 0.1;
+        const SEED: u64 = 0x4f1b_bcdc_bfa5_3e0b;
+        // genomes scored in about the time one bfs node is expanded
+        const GENOMES_PER_NODE: usize = 6;
+
+        #[derive(Clone)]
+        struct Genome {
+            actions: Vec<Action>,
+            // best `value` over the genome's prefixes, and that prefix's length
+            fitness: f64,
+            plan_len: usize,
+        }
+
+        // makes every gene legal in turn and scores each prefix; a genome may
+        // come out shorter when a state has no legal action at all
+        fn repair(
+            state: &State,
+            mut actions: Vec<Action>,
+            value: &impl Fn(&State, usize) -> f64,
+            rng: &mut Rng,
+        ) -> Genome {
+            let mut state = state.clone();
+            let mut fitness = f64::MIN;
+            let mut plan_len = 0;
+            actions.resize(GENOME_LEN, Action::Wait);
+            for i in 0..GENOME_LEN {
+                // genes outlive the states they were legal on: keep the ones
+                // that still are, redraw the rest from what is legal now
+                match state.validate(actions[i]) {
+                    Ok(gene) if actions[i] != Action::Wait => state.apply(gene),
+                    _ => {
+                        let legal = state.get_possible_actions();
+                        if legal.is_empty() {
+                            actions.truncate(i);
+                            break;
+                        }
+                        actions[i] = legal[rng.below(legal.len())];
+                        state.apply_unchecked(actions[i]);
+                    }
+                }
+                let prefix_value = value(&state, i + 1);
+                if prefix_value > fitness {
+                    fitness = prefix_value;
+                    plan_len = i + 1;
+                }
+            }
+            Genome {
+                actions,
+                fitness,
+                plan_len,
+            }
+        }
+
+        // the fitter of two random genomes
+        fn tournament<'a>(population: &'a [Genome], rng: &mut Rng) -> &'a Genome {
+            let a = &population[rng.below(population.len())];
+            let b = &population[rng.below(population.len())];
+            if a.fitness >= b.fitness {
+                a
+            } else {
+                b
+            }
+        }
+
+        fn offspring(mother: &Genome, father: &Genome, rng: &mut Rng) -> Vec<Action> {
+            let cut = rng.below(GENOME_LEN + 1);
+            let mut actions: Vec<Action> = mother.actions.iter().take(cut).copied().collect();
+            actions.extend(father.actions.iter().skip(cut));
+            for gene in actions.iter_mut() {
+                if rng.next_f64() < MUTATION_RATE {
+                    // repair picks the replacement
+                    *gene = Action::Wait;
+                }
+            }
+            actions
+        }
+
+        // `value` scores the state a plan prefix ends in, given its length;
+        // evolves until `out_of_budget` says stop, keeping the best genome.
+        // A non-empty `seed` fills half the first generation with itself and
+        // its mutants, the rest starts random; `rng_seed` varies the run.
+        pub fn search(
+            state: &State,
+            seed: &[Action],
+            rng_seed: u64,
+            value: impl Fn(&State, usize) -> f64,
+            out_of_budget: impl Fn(&SearchStats) -> bool,
+        ) -> SearchResult {
+            let mut rng = Rng::new(SEED ^ rng_seed);
+            let mut stats = SearchStats::default();
+            let mut population = Vec::with_capacity(POPULATION);
+            if !seed.is_empty() {
+                let seed = repair(state, seed.to_vec(), &value, &mut rng);
+                while population.len() < POPULATION / 2 {
+                    let mutant = offspring(&seed, &seed, &mut rng);
+                    population.push(repair(state, mutant, &value, &mut rng));
+                }
+                population[0] = seed;
+            }
+            while population.len() < POPULATION {
+                population.push(repair(state, Vec::new(), &value, &mut rng));
+            }
+            let mut genomes = POPULATION;
+            stats.nodes = genomes / GENOMES_PER_NODE;
+
+            while !out_of_budget(&stats) {
+                population.sort_by(|a, b| b.fitness.partial_cmp(&a.fitness).unwrap());
+                // elitism: the best genome always survives unchanged
+                let mut next = vec![population[0].clone()];
+                while next.len() < POPULATION {
+                    let child = offspring(
+                        tournament(&population, &mut rng),
+                        tournament(&population, &mut rng),
+                        &mut rng,
+                    );
+                    next.push(repair(state, child, &value, &mut rng));
+                }
+                genomes += POPULATION - 1;
+                stats.nodes = genomes / GENOMES_PER_NODE;
+                population = next;
+            }
+
+            let best = population
+                .iter()
+                .max_by(|a, b| a.fitness.partial_cmp(&b.fitness).unwrap())
+                .unwrap();
+            stats.max_depth = best.plan_len;
+
+            let mut root_values = RootValues::default();
+            for genome in population.iter().filter(|g| g.plan_len > 0) {
+                root_values.record(genome.actions[0], genome.fitness);
+            }
+            let mut plan = best.actions[..best.plan_len].to_vec();
+            plan.reverse();
+            SearchResult::new(plan, root_values, stats)
+        }
+    }
+}
+
+// The action chosen for a turn and what the search spent getting there.
+#[derive(Debug, Clone)]
+struct Decision {
+    action: Action,
+    stats: SearchStats,
+    // the whole plan the action starts, and the ranked root alternatives
+    plan: Vec<Action>,
+    root_values: Vec<(Action, f64)>,
+    alternatives: Vec<RankedPlan>,
+    survivors: Vec<Vec<Action>>,
+}
+
+impl Decision {
+    fn new(action: Action, stats: SearchStats) -> Self {
+        Self {
+            action,
+            stats,
+            plan: vec![action],
+            root_values: Vec::new(),
+            alternatives: Vec::new(),
+            survivors: Vec::new(),
+        }
+    }
+
+    // `result` must hold a non-empty plan
+    fn from_search(result: SearchResult) -> Self {
+        Self {
+            action: result.plan[0],
+            stats: result.stats,
+            root_values: result.root_values.sorted(),
+            plan: result.plan,
+            alternatives: result.alternatives,
+            survivors: result.survivors,
+        }
+    }
+
+    // decided without searching
+    fn immediate(action: Action) -> Self {
+        Self::new(action, SearchStats::default())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new(
+            Duration::from_millis(FIRST_TURN_BUDGET_MS),
+            Duration::from_millis(TURN_BUDGET_MS),
+        )
+    }
+}
+
+// the judge allows 1000ms for the first answer and 50ms for each one after;
+// later turns keep a margin for parsing, output and a late scheduler
+const FIRST_TURN_BUDGET_MS: u64 = 1000;
+const TURN_BUDGET_MS: u64 = 40;
+
+// cumulative fractions of the turn budget available to each stage of Bot::think
+const FULL_SEARCH_SHARE: f64 = 0.7;
+const SHALLOW_SEARCH_SHARE: f64 = 0.9;
+const SHALLOW_SEARCH_DEPTH: usize = 2;
+
+const CROSS_CHECK_ROLLOUT_DEPTH: usize = 8;
+// two rupees' worth, in evaluation units
+const CROSS_CHECK_THRESHOLD: f64 = 2.0 * SCORE_WEIGHT;
+
+const CALIBRATION_READS: u32 = 1000;
+// give up looking for a tick after this many reads: the clock may be frozen
+const CALIBRATION_SPINS: u32 = 1_000_000;
+// search nodes timed on the first state to get a node rate
+const CALIBRATION_NODES: usize = 2000;
+// node rate assumed when the clock can't time the calibration nodes, below
+// what a slow judge manages on a late-game board
+const FALLBACK_NODES_PER_MS: f64 = 100.0;
+
+// xorshift64*, enough randomness for sampling without an external crate
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // xorshift never leaves zero
+        Self(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // uniform in (0, 1), never exactly 0 so it is safe to take a log of
+    fn next_f64(&mut self) -> f64 {
+        ((self.next_u64() >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+    }
+
+    // uniform in 0..n
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    // standard Gumbel noise
+    fn gumbel(&mut self) -> f64 {
+        -(-self.next_f64().ln()).ln()
+    }
+}
+
+// How far Instant can be trusted on this machine, and how many search nodes
+// it expands per millisecond.
+#[derive(Debug, Copy, Clone)]
+struct ClockCalibration {
+    read_cost: Duration,
+    // smallest step between two readings; None when the clock never moved
+    resolution: Option<Duration>,
+    // None when the clock couldn't time the calibration nodes
+    nodes_per_ms: Option<f64>,
+}
+
+impl ClockCalibration {
+    fn measure(state: &State) -> Self {
+        let start = Instant::now();
+        for _ in 0..CALIBRATION_READS {
+            let _ = Instant::now();
+        }
+        let read_cost = start.elapsed() / CALIBRATION_READS;
+
+        let first = Instant::now();
+        let resolution = (0..CALIBRATION_SPINS)
+            .map(|_| Instant::now())
+            .find(|&now| now != first)
+            .map(|now| now - first);
+
+        let start = Instant::now();
+        let nodes = Self::expand_nodes(state, CALIBRATION_NODES);
+        let elapsed_ms = start.elapsed().as_secs_f64() * 1000.0;
+        let nodes_per_ms = Some(nodes as f64 / elapsed_ms)
+            .filter(|_| resolution.is_some() && elapsed_ms > 0.0 && nodes > 0);
+
+        Self {
+            read_cost,
+            resolution,
+            nodes_per_ms,
+        }
+    }
+
+    // breadth-first from `state` the way the tree searches go, expanding,
+    // hashing and evaluating up to `limit` nodes; the count expanded
+    fn expand_nodes(state: &State, limit: usize) -> usize {
+        let weights = &features::WEIGHTS;
+        let mut cast_cache = CastCache::new();
+        let mut stats = SearchStats::default();
+        let mut visited = HashSet::new();
+        let mut queue = LinkedList::new();
+        queue.push_back(state.clone());
+        let mut nodes = 0;
+        while let Some(current) = queue.pop_front() {
+            if nodes == limit {
+                break;
+            }
+            nodes += 1;
+            for (_, next, _) in expand(&current, None, &mut cast_cache, &mut stats) {
+                std::hint::black_box(next.evaluate(weights));
+                if visited.insert(next.clone()) {
+                    queue.push_back(next);
+                }
+            }
+        }
+        nodes
+    }
+
+    // a coarse clock can report a deadline up to one tick late
+    fn safety_margin(self) -> Duration {
+        self.read_cost + self.resolution.unwrap_or_default()
+    }
+}
+
+// a fully committed bot discounts each extra plan step by this fraction
+const RISK_DEPTH_DISCOUNT: f64 = 0.05;
+// margin, in rupees, at which the bot is fully risk-averse (or risk-seeking)
+const RISK_MARGIN_SCALE: f64 = 20.0;
+// engine gap, in rupees per turn, at which pacing alone is worth this much risk;
+// a stronger opponent engine wins long games, so hurry against one
+const ENGINE_GAP_SCALE: f64 = 1.0;
+const ENGINE_PACING_RISK: f64 = 0.5;
+
+#[derive(Clone)]
+struct Bot {
+    config: Config,
+    // -1.0 (behind, chase long high-upside plans) to 1.0 (ahead, bank short ones)
+    risk: f64,
+    // shaved off every search budget, from ClockCalibration
+    clock_margin: Duration,
+    // from ClockCalibration, for node_cap
+    nodes_per_ms: f64,
+    // nodes this turn's finished stages expanded, see out_of_budget
+    spent: Cell<usize>,
+    // this turn's opponent, for the two-player searches
+    enemy: Option<Player>,
+    turns_left: u32,
+    // searches get Config::first_turn_budget instead of time_budget
+    first_turn: bool,
+    // of the turn budget, for Bot::estimate_races; see set_race_share
+    race_share: f64,
+    // the rest of last turn's plan once its first action was played: what
+    // SearchMode::Rhea starts from and Config::reuse_plans replays
+    plan_tail: Vec<Action>,
+    // likewise for the beam survivors that started with that action
+    warm_plans: Vec<Vec<Action>>,
+    // credited with each plan_tail, see Config::history
+    history: History,
+    // the opponent's side of estimate_races
+    races: RaceCache,
+    kept: Kept,
+}
+
+// The last bfs's tree and the frontier it left unexpanded. When the new
+// board is one that tree reached in one move, which Order's equality allows
+// while the opponent only casts or rests, the next bfs starts from the part
+// of the frontier below it. A clone starts without one.
+#[derive(Default)]
+struct Kept(RefCell<Option<(SearchTree, LinkedList<Node>)>>);
+
+impl Clone for Kept {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl Bot {
+    fn new(config: Config) -> Self {
+        Self {
+            config,
+            risk: 0.0,
+            clock_margin: Duration::default(),
+            nodes_per_ms: FALLBACK_NODES_PER_MS,
+            spent: Cell::new(0),
+            enemy: None,
+            turns_left: MAX_TURNS,
+            first_turn: true,
+            race_share: RACE_SHARE,
+            plan_tail: Vec::new(),
+            warm_plans: Vec::new(),
+            history: History::default(),
+            races: RaceCache::default(),
+            kept: Kept::default(),
+        }
+    }
+
+    fn calibrate(&mut self, state: &State) {
+        let clock = ClockCalibration::measure(state);
+        match clock.resolution {
+            Some(resolution) => eprintln!(
+                "clock: {:?} per read, {:?} resolution",
+                clock.read_cost, resolution
+            ),
+            None => eprintln!("clock: never advanced, relying on node caps"),
+        }
+        self.clock_margin = clock.safety_margin();
+        self.nodes_per_ms = clock.nodes_per_ms.unwrap_or(FALLBACK_NODES_PER_MS);
+        eprintln!(
+            "node caps: {} on turn one, {} per turn after",
+            self.node_cap(self.config.first_turn_budget),
+            self.node_cap(self.config.time_budget)
+        );
+    }
+
+    // nodes a search may expand within `budget`, as a second limit for when
+    // the clock can't be trusted to stop it
+    fn node_cap(&self, budget: Duration) -> usize {
+        let calibrated = (self.nodes_per_ms * budget.as_secs_f64() * 1000.0) as usize;
+        calibrated.min(self.config.max_nodes)
+    }
+
+    fn turn_budget(&self) -> Duration {
+        if self.first_turn {
+            self.config.first_turn_budget
+        } else {
+            self.config.time_budget
+        }
+    }
+
+    fn search_budget(&self, share: f64) -> Duration {
+        self.turn_budget()
+            .mul_f64(share)
+            .saturating_sub(self.clock_margin)
+    }
+
+    // margin matters more the closer the game is to its end
+    fn set_risk(&mut self, margin: i32, turns_left: u32, engine_gap: f64) {
+        let lead = (margin as f64 / RISK_MARGIN_SCALE).clamp(-1.0, 1.0);
+        let progress = 1.0 - turns_left as f64 / MAX_TURNS as f64;
+        let pacing = (engine_gap / ENGINE_GAP_SCALE).clamp(-1.0, 1.0) * ENGINE_PACING_RISK;
+        self.risk = (lead * progress + pacing).clamp(-1.0, 1.0);
+    }
+
+    // how much of the turn opponent modeling gets before my own search: the
+    // closer the score and the later the game, the more each race decides
+    fn set_race_share(&mut self, margin: i32, turns_left: u32) {
+        let progress = 1.0 - turns_left as f64 / MAX_TURNS as f64;
+        let closeness = 1.0 - (margin.abs() as f64 / RISK_MARGIN_SCALE).min(1.0);
+        self.race_share = RACE_SHARE + (RACE_MAX_SHARE - RACE_SHARE) * closeness * progress;
+    }
+
+    // discounts values found deeper in the search by the tempo discount, then
+    // further (ahead) or less (behind) by the risk appetite
+    fn risk_adjusted(&self, value: f64, depth: usize) -> f64 {
+        let per_turn = (1.0 - self.config.tempo_discount) * (1.0 - RISK_DEPTH_DISCOUNT * self.risk);
+        value * per_turn.powi(depth as i32)
+    }
+
+    // `budget` runs from the start of the turn, so like the clock the node
+    // cap counts what the turn's earlier stages spent
+    fn out_of_budget(
+        &self,
+        start_instant: &Instant,
+        budget: Duration,
+        stats: &SearchStats,
+    ) -> bool {
+        self.spent.get() + stats.nodes >= self.node_cap(budget) || start_instant.elapsed() > budget
+    }
+
+    fn charge(&self, stats: &SearchStats) {
+        self.spent.set(self.spent.get() + stats.nodes);
+    }
+
+    // score for the plan searches that rewrite whole action sequences: a
+    // plain value would just grow plans that hoard ingredients
+    fn gain_per_turn(&self, start_value: f64, end: &State, len: usize) -> f64 {
+        let end_value = self.risk_adjusted(end.evaluate(&self.config.weights), len);
+        (end_value - start_value) / len.max(1) as f64
+    }
+
+    // runs the search picked in the config, on several threads when a
+    // parallel build asks for them
+    fn search(
+        &self,
+        start_instant: &Instant,
+        state: &State,
+        budget: Duration,
+        max_depth: usize,
+    ) -> SearchResult {
+        let spent = self.spent.get();
+        #[cfg(feature = "parallel")]
+        let result = if self.config.threads > 1 {
+            self.root_parallel(start_instant, state, budget, max_depth)
+        } else {
+            self.search_once(start_instant, state, budget, max_depth)
+        };
+        #[cfg(not(feature = "parallel"))]
+        let result = self.search_once(start_instant, state, budget, max_depth);
+        // searches made of several runs charge as they go; their reported
+        // total already includes those runs
+        self.spent.set(spent + result.stats.nodes);
+        result
+    }
+
+    // Root parallelization for offline runs (the judge gives one thread):
+    // each worker runs the configured search with its own RNG seed and
+    // votes for its first action. The plan comes from the first worker
+    // backing the most voted action. Deterministic searches all agree, so
+    // this only pays off with the randomized ones.
+    #[cfg(feature = "parallel")]
+    fn root_parallel(
+        &self,
+        start_instant: &Instant,
+        state: &State,
+        budget: Duration,
+        max_depth: usize,
+    ) -> SearchResult {
+        let results: Vec<SearchResult> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.config.threads)
+                .map(|worker| {
+                    let mut bot = self.clone();
+                    bot.config.seed ^= (worker as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+                    scope.spawn(move || bot.search_once(start_instant, state, budget, max_depth))
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("search worker panicked"))
+                .collect()
+        });
+
+        let mut tally: Vec<(Action, usize)> = Vec::new();
+        for action in results.iter().filter_map(|result| result.plan.first()) {
+            match tally.iter_mut().find(|(a, _)| a == action) {
+                Some((_, votes)) => *votes += 1,
+                None => tally.push((*action, 1)),
+            }
+        }
+        let winner = tally
+            .iter()
+            .fold(
+                None,
+                |best: Option<(Action, usize)>, &(action, votes)| match best {
+                    Some((_, best_votes)) if best_votes >= votes => best,
+                    _ => Some((action, votes)),
+                },
+            )
+            .map(|(action, _)| action);
+
+        let nodes = results.iter().map(|result| result.stats.nodes).sum();
+        let mut result = results
+            .into_iter()
+            .find(|result| result.plan.first().copied() == winner)
+            .expect("the winner comes from some worker");
+        result.stats.nodes = nodes;
+        result
+    }
+
+    fn search_once(
+        &self,
+        start_instant: &Instant,
+        state: &State,
+        budget: Duration,
+        max_depth: usize,
+    ) -> SearchResult {
+        match self.config.search {
+            SearchMode::Bfs => self.bfs(start_instant, state, budget, max_depth),
+            SearchMode::Beam => {
+                let width = self.config.beam_width;
+                self.beam(start_instant, state, budget, max_depth, width, 0.0)
+            }
+            SearchMode::StochasticBeam => {
+                let width = self.config.beam_width;
+                let temperature = self.config.beam_temperature;
+                self.beam(start_instant, state, budget, max_depth, width, temperature)
+            }
+            SearchMode::Chokudai => self.chokudai(start_instant, state, budget, max_depth),
+            SearchMode::Ensemble => self.ensemble(start_instant, state, budget, max_depth),
+            SearchMode::Iddfs => self.iddfs(start_instant, state, budget, max_depth),
+            SearchMode::Dfbnb => self.dfbnb(start_instant, state, budget, max_depth),
+            SearchMode::AStar => self.astar(start_instant, state, budget, max_depth),
+            SearchMode::BestFirst => self.best_first(start_instant, state, budget, max_depth),
+            SearchMode::Paranoid => self.bfs(start_instant, state, budget, max_depth),
+            SearchMode::Minimax | SearchMode::AlphaBeta => match self.enemy.as_ref() {
+                Some(enemy) => {
+                    let plies = max_depth.min(MINIMAX_MAX_PLIES);
+                    let pruning = self.config.search == SearchMode::AlphaBeta;
+                    let weights = &self.config.weights;
+                    search::minimax::search(state, enemy, weights, plies, pruning, |stats| {
+                        self.out_of_budget(start_instant, budget, stats)
+                    })
+                }
+                None => self.bfs(start_instant, state, budget, max_depth),
+            },
+            SearchMode::Annealing => {
+                let seed_budget = budget.mul_f64(ANNEALING_SEED_SHARE);
+                let seed = self.bfs(start_instant, state, seed_budget, max_depth);
+                self.charge(&seed.stats);
+                let start_value = state.evaluate(&self.config.weights);
+                search::annealing::improve(
+                    state,
+                    seed,
+                    self.config.seed,
+                    |end, len| self.gain_per_turn(start_value, end, len),
+                    |stats| self.out_of_budget(start_instant, budget, stats),
+                )
+            }
+            SearchMode::Rhea => {
+                let start_value = state.evaluate(&self.config.weights);
+                search::genetic::search(
+                    state,
+                    &self.plan_tail,
+                    self.config.seed,
+                    |end, len| self.gain_per_turn(start_value, end, len),
+                    |stats| self.out_of_budget(start_instant, budget, stats),
+                )
+            }
+            SearchMode::Genetic => {
+                let start_value = state.evaluate(&self.config.weights);
+                search::genetic::search(
+                    state,
+                    &[],
+                    self.config.seed,
+                    |end, len| self.gain_per_turn(start_value, end, len),
+                    |stats| self.out_of_budget(start_instant, budget, stats),
+                )
+            }
+            SearchMode::Mcts => {
+                let weights = &self.config.weights;
+                let bias = self.config.rollout_bias;
+                let mut rng = Rng::new(ROLLOUT_SEED ^ self.config.seed);
+                let biased = self.config.biased_rollouts;
+                search::mcts::search(
+                    state,
+                    max_depth,
+                    &self.config.mcts,
+                    |child| child.evaluate(weights),
+                    |leaf, depth| {
+                        if biased {
+                            rollout::playout(leaf, weights, depth, &bias, &mut rng)
+                        } else {
+                            leaf.rollout_value(weights, depth)
+                        }
+                    },
+                    |stats| self.out_of_budget(start_instant, budget, stats),
+                )
+            }
+        }
+    }
+
+    // stops expanding past `max_depth` or once `budget` has elapsed since
+    // `start_instant`. In SearchMode::Paranoid the opponent takes an order
+    // off the board every move, see State::lose_order_to.
+    fn bfs(
+        &self,
+        start_instant: &Instant,
+        state: &State,
+        budget: Duration,
+        max_depth: usize,
+    ) -> SearchResult {
+        let weights = &self.config.weights;
+        let mut tree = SearchTree::new(state, state.evaluate(weights), &self.config);
+        tree.history = self.expansion_history();
+        let mut queue = LinkedList::<Node>::new();
+        let mut root = Node::root(state.clone());
+        if let SearchMode::Paranoid = self.config.search {
+            root.rival = self.enemy.as_ref().map(|enemy| enemy.inventory);
+        }
+        let keep = self.config.reuse_tree && self.config.search == SearchMode::Bfs;
+        if let Some((kept, frontier)) = self.kept.0.take().filter(|_| keep) {
+            queue = kept.reroot(frontier, &mut tree);
+            if self.config.explain && !queue.is_empty() {
+                eprintln!("reusing {} frontier nodes of last turn's tree", queue.len());
+            }
+        }
+        if queue.is_empty() {
+            queue.push_back(root);
+        }
+
+        while let Some(node) = queue.pop_front() {
+            if self.out_of_budget(start_instant, budget, &tree.stats) {
+                // time over; the node stays on the frontier for Kept
+                queue.push_front(node);
+                break;
+            }
+            tree.count(&node);
+
+            if tree.is_goal(&node.state) {
+                eprintln!("{} game states visited", tree.stats.nodes);
+                let result = tree.take_result(Some(&node.state));
+                if keep {
+                    queue.push_front(node);
+                    self.kept.0.replace(Some((tree, queue)));
+                }
+                return result;
+            }
+
+            let value = self.risk_adjusted(node.state.evaluate(weights), node.depth);
+            tree.consider(&node, value);
+
+            if node.depth >= max_depth {
+                continue;
+            }
+
+            for (action, mut next, next_cast) in tree.expand(&node) {
+                let mut rival = node.rival;
+                if let Some(rival) = rival.as_mut() {
+                    next.lose_order_to(rival);
+                }
+                if tree.admit(&node.state, action, &next) {
+                    let next_root = node.root.unwrap_or(action);
+                    let value = self.risk_adjusted(next.value_with_brew(weights), node.depth + 1);
+                    tree.root_values.record(next_root, value);
+                    let mut child = Node::new(next, next_root, node.depth + 1, next_cast);
+                    child.rival = rival;
+                    queue.push_back(child);
+                    tree.stats.note_frontier(queue.len());
+                }
+            }
+
+            if !self.config.macros {
+                continue;
+            }
+            // each state along a macro joins the queue now instead of a level
+            // or two later, so the chain reaches brews in fewer levels
+            for chain in macro_chains(&node.state) {
+                let mut parent = node.state.clone();
+                let mut rival = node.rival;
+                for (step, &action) in chain.iter().enumerate() {
+                    let depth = node.depth + step + 1;
+                    if depth > max_depth {
+                        break;
+                    }
+                    let mut next = parent.clone();
+                    next.apply_unchecked(action);
+                    if let Some(rival) = rival.as_mut() {
+                        next.lose_order_to(rival);
+                    }
+                    if tree.link_macro(&parent, action, &next) {
+                        let next_root = node.root.unwrap_or(chain[0]);
+                        let mut child = Node::new(next.clone(), next_root, depth, None);
+                        child.rival = rival;
+                        queue.push_back(child);
+                        tree.stats.note_frontier(queue.len());
+                    }
+                    parent = next;
+                }
+            }
+        }
+
+        let result = tree.take_result(None);
+        if keep {
+            self.kept.0.replace(Some((tree, queue)));
+        }
+        result
+    }
+
+    // like bfs, but always expands the most valuable state on the frontier
+    // first, so promising lines get deep before the budget runs out
+    fn best_first(
+        &self,
+        start_instant: &Instant,
+        state: &State,
+        budget: Duration,
+        max_depth: usize,
+    ) -> SearchResult {
+        let weights = &self.config.weights;
+        let mut tree = SearchTree::new(state, state.evaluate(weights), &self.config);
+        tree.history = self.expansion_history();
+        let mut frontier = BinaryHeap::new();
+        frontier.push(Ranked {
+            value: state.evaluate(weights),
+            node: Node::root(state.clone()),
+        });
+
+        while let Some(Ranked { node, .. }) = frontier.pop() {
+            if self.out_of_budget(start_instant, budget, &tree.stats) {
+                break;
+            }
+            tree.count(&node);
+
+            if tree.is_goal(&node.state) {
+                eprintln!("{} game states visited", tree.stats.nodes);
+                return tree.take_result(Some(&node.state));
+            }
+
+            let value = self.risk_adjusted(node.state.evaluate(weights), node.depth);
+            tree.consider(&node, value);
+
+            if node.depth >= max_depth {
+                continue;
+            }
+
+            for (action, next, next_cast) in tree.expand(&node) {
+                if tree.link(&node.state, action, &next) {
+                    let next_root = node.root.unwrap_or(action);
+                    let value = self.risk_adjusted(next.value_with_brew(weights), node.depth + 1);
+                    tree.root_values.record(next_root, value);
+                    frontier.push(Ranked {
+                        value,
+                        node: Node::new(next, next_root, node.depth + 1, next_cast),
+                    });
+                    tree.stats.note_frontier(frontier.len());
+                }
+            }
+        }
+
+        tree.take_result(None)
+    }
+
+    // Chokudai-style restarts: beams of doubling width from 1 until the
+    // budget runs out, planning from the widest run that finished
+    fn chokudai(
+        &self,
+        start_instant: &Instant,
+        state: &State,
+        budget: Duration,
+        max_depth: usize,
+    ) -> SearchResult {
+        let mut nodes = 0;
+        let mut finished: Option<SearchResult> = None;
+        let mut width = 1;
+        while width <= CHOKUDAI_MAX_WIDTH {
+            let result = self.beam(start_instant, state, budget, max_depth, width, 0.0);
+            self.charge(&result.stats);
+            nodes += result.stats.nodes;
+            let timed_out = self.out_of_budget(start_instant, budget, &result.stats);
+            if !timed_out || finished.is_none() {
+                finished = Some(result);
+            }
+            if timed_out {
+                break;
+            }
+            width *= 2;
+        }
+
+        let mut result = finished.expect("width 1 always runs");
+        result.stats.nodes = nodes;
+        result
+    }
+
+    // like bfs, but keeps only the `width` best states of each depth, so it
+    // reaches deeper plans at the cost of missing some. A positive temperature
+    // samples the survivors instead, so near-identical inventories don't crowd
+    // out everything else.
+    fn beam(
+        &self,
+        start_instant: &Instant,
+        state: &State,
+        budget: Duration,
+        max_depth: usize,
+        width: usize,
+        temperature: f64,
+    ) -> SearchResult {
+        let weights = &self.config.weights;
+        let mut rng = Rng::new(STOCHASTIC_BEAM_SEED ^ self.config.seed);
+        let mut tree = SearchTree::new(state, state.evaluate(weights), &self.config);
+        tree.history = self.expansion_history();
+        let mut beam = vec![Node::root(state.clone())];
+        beam.extend(self.warm_start_nodes(&mut tree, state, max_depth));
+        let mut survivors = Vec::new();
+
+        'search: while !beam.is_empty() {
+            let mut children = Vec::<(f64, Node)>::new();
+            for node in beam {
+                if self.out_of_budget(start_instant, budget, &tree.stats) {
+                    break 'search;
+                }
+                tree.count(&node);
+
+                if tree.is_goal(&node.state) {
+                    eprintln!("{} game states visited", tree.stats.nodes);
+                    let survivors = tree.plans_to(&survivors);
+                    let mut result = tree.take_result(Some(&node.state));
+                    result.survivors = survivors;
+                    return result;
+                }
+
+                let value = self.risk_adjusted(node.state.evaluate(weights), node.depth);
+                tree.consider(&node, value);
+
+                if node.depth >= max_depth {
+                    continue;
+                }
+
+                for (action, next, next_cast) in tree.expand(&node) {
+                    if tree.link(&node.state, action, &next) {
+                        let next_root = node.root.unwrap_or(action);
+                        let value =
+                            self.risk_adjusted(next.value_with_brew(weights), node.depth + 1);
+                        tree.root_values.record(next_root, value);
+                        let child = Node::new(next, next_root, node.depth + 1, next_cast);
+                        children.push((value, child));
+                        tree.stats.note_frontier(children.len());
+                    }
+                }
+            }
+
+            if temperature > 0.0 {
+                // Gumbel-top-k: the `width` largest perturbed values are a
+                // sample without replacement weighted by exp(value / temperature)
+                for child in children.iter_mut() {
+                    child.0 += temperature * rng.gumbel();
+                }
+            }
+            children.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+            children.truncate(width);
+            if !children.is_empty() {
+                survivors = children
+                    .iter()
+                    .take(self.config.warm_start)
+                    .map(|(_, node)| node.state.clone())
+                    .collect();
+            }
+            beam = children.into_iter().map(|(_, node)| node).collect();
+        }
+
+        let survivors = tree.plans_to(&survivors);
+        let mut result = tree.take_result(None);
+        result.survivors = survivors;
+        result
+    }
+
+    // last turn's beam survivors replayed from this turn's state, so the
+    // beam starts from where it got to instead of from scratch. Plans the
+    // new board no longer allows (a taken order, a spent ingredient) drop out.
+    fn warm_start_nodes(
+        &self,
+        tree: &mut SearchTree,
+        state: &State,
+        max_depth: usize,
+    ) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        'plans: for plan in self
+            .warm_plans
+            .iter()
+            .filter(|plan| plan.len() <= max_depth)
+        {
+            let mut parent = state.clone();
+            let mut linked = false;
+            for &action in plan {
+                let mut next = parent.clone();
+                if next.try_apply(action).is_err() {
+                    continue 'plans;
+                }
+                linked = tree.link(&parent, action, &next);
+                parent = next;
+            }
+            // an end state already in the tree is searched from there
+            if linked {
+                nodes.push(Node::new(parent, plan[0], plan.len(), None));
+            }
+        }
+        nodes
+    }
+
+    // the evaluation of the state `plan` ends in, split into named terms
+    fn explain_leaf(&self, state: &State, plan: &[Action]) {
+        let mut leaf = state.clone();
+        for &action in plan {
+            if leaf.try_apply(action).is_err() {
+                break;
+            }
+        }
+        let weights = &self.config.weights;
+        eprintln!("  leaf value {:.2}:", leaf.evaluate(weights));
+        for (name, value) in features::decompose(&leaf, weights) {
+            if value != 0.0 {
+                eprintln!("    {:<16} {:>8.2}", name, value);
+            }
+        }
+    }
+
+    // the move-ordering table a search starts from, when enabled
+    fn expansion_history(&self) -> Option<History> {
+        Some(self.history.clone()).filter(|_| self.config.history)
+    }
+
+    // depth-first passes with a growing depth limit; only a pass that
+    // finished counts, so the plan always comes from a fully searched horizon
+    fn iddfs(
+        &self,
+        start_instant: &Instant,
+        state: &State,
+        budget: Duration,
+        max_depth: usize,
+    ) -> SearchResult {
+        let mut stats = SearchStats::default();
+        let mut completed: Option<DepthFirst> = None;
+        let mut history = self.expansion_history();
+
+        for limit in 1..=max_depth {
+            let mut pass = DepthFirst::new(self, start_instant, budget, limit);
+            pass.stats = stats;
+            pass.history = history.clone();
+            pass.visit(state, None);
+            stats = pass.stats;
+
+            // the previous pass found no goal, so this one is as shallow as any
+            if let Some(mut goal) = pass.goal {
+                eprintln!("{} game states visited", stats.nodes);
+                goal.reverse();
+                return SearchResult::new(goal, pass.root_values, stats);
+            }
+            if pass.timed_out {
+                break;
+            }
+            let exhausted = !pass.cut;
+            // the next, deeper pass tries this pass's best line first
+            if let Some(history) = history.as_mut() {
+                history.credit(&pass.best_plan);
+            }
+            completed = Some(pass);
+            if exhausted {
+                break;
+            }
+        }
+
+        match completed {
+            Some(pass) => {
+                let mut plan = pass.stale_goal.unwrap_or(pass.best_plan);
+                plan.reverse();
+                SearchResult::new(plan, pass.root_values, stats)
+            }
+            None => SearchResult::new(Vec::new(), RootValues::default(), stats),
+        }
+    }
+
+    // depth-first branch and bound over the next DFBNB_HORIZON moves (fewer
+    // if `max_depth` says so), for the most rupees by the horizon
+    fn dfbnb(
+        &self,
+        start_instant: &Instant,
+        state: &State,
+        budget: Duration,
+        max_depth: usize,
+    ) -> SearchResult {
+        let horizon = max_depth.min(DFBNB_HORIZON);
+        let mut search = BranchAndBound::new(self, start_instant, budget, horizon);
+        search.visit(state, None);
+        eprintln!("{} game states visited", search.stats.nodes);
+
+        let mut plan = search.best_plan;
+        plan.reverse();
+        SearchResult::new(plan, search.root_values, search.stats)
+    }
+
+    // sets each order's claim by racing our fewest turns to it against the
+    // opponent's on the same board. Out of time, a missing entry may just be
+    // unexplored, so only orders both searches settled get a new claim
+    fn estimate_races(&mut self, start_instant: &Instant, state: &mut State, enemy: &Player) {
+        let budget = self.search_budget(self.race_share);
+        let mut rival = state.clone();
+        rival.me = enemy.clone();
+        let cached = self.races.get(&rival, self.turns_left);
+        let out_of_budget = |stats: &SearchStats| self.out_of_budget(start_instant, budget, stats);
+
+        let mut stats = SearchStats::default();
+        let mine =
+            search::dijkstra::turns_to_orders(state, RACE_HORIZON, &mut stats, out_of_budget);
+        let searched = cached.is_none();
+        if !searched && self.config.explain {
+            eprintln!("races: opponent position seen recently, reusing its turns to orders");
+        }
+        let theirs = cached.unwrap_or_else(|| {
+            search::dijkstra::turns_to_orders(&rival, RACE_HORIZON, &mut stats, out_of_budget)
+        });
+        let complete = !out_of_budget(&stats);
+        self.charge(&stats);
+        if searched && complete {
+            self.races.insert(&rival, self.turns_left, &theirs);
+        }
+
+        for order in state.orders.iter_mut() {
+            let chance = match (mine.get(&order.id), theirs.get(&order.id)) {
+                (Some(&mine), Some(&theirs)) => {
+                    1.0 / (1.0 + ((mine as f64 - theirs as f64) / RACE_SPREAD).exp())
+                }
+                _ if !complete => continue,
+                (None, Some(_)) => 0.0,
+                _ => 1.0,
+            };
+            order.claim = (chance * 100.0).round() as u8;
+        }
+    }
+
+    // an A* plan toward every order, keeping the one with the most rupees per turn
+    fn astar(
+        &self,
+        start_instant: &Instant,
+        state: &State,
+        budget: Duration,
+        max_depth: usize,
+    ) -> SearchResult {
+        let mut stats = SearchStats::default();
+        let mut best: Option<(f64, Vec<Action>)> = None;
+        for &order in state.orders.iter() {
+            let plan = search::astar::plan_to_order(state, order, max_depth, &mut stats, |stats| {
+                self.out_of_budget(start_instant, budget, stats)
+            });
+            if let Some(plan) = plan {
+                let rate = order.target_value() / plan.len() as f64;
+                if best.as_ref().is_none_or(|(best_rate, _)| rate > *best_rate) {
+                    best = Some((rate, plan));
+                }
+            }
+        }
+
+        let mut plan = best.map(|(_, plan)| plan).unwrap_or_default();
+        plan.reverse();
+        SearchResult::new(plan, RootValues::default(), stats)
+    }
+
+    // each planner gets an equal slice of the budget and votes for its first
+    // action; the plan returned is the one from the best-voted planner that
+    // backs the winning action
+    fn ensemble(
+        &self,
+        start_instant: &Instant,
+        state: &State,
+        budget: Duration,
+        max_depth: usize,
+    ) -> SearchResult {
+        let planners = if self.enemy.is_some() { 3 } else { 2 };
+        let slice = |i: usize| budget.mul_f64(i as f64 / planners as f64);
+        let width = ENSEMBLE_BEAM_WIDTH;
+        let ballot = |vote, result: SearchResult| {
+            self.charge(&result.stats);
+            (vote, result)
+        };
+        let mut ballots = vec![ballot(
+            ENSEMBLE_BEAM_VOTE,
+            self.beam(start_instant, state, slice(1), max_depth, width, 0.0),
+        )];
+        ballots.push(ballot(
+            ENSEMBLE_ASTAR_VOTE,
+            self.astar(start_instant, state, slice(2), max_depth),
+        ));
+        if let Some(enemy) = self.enemy.as_ref() {
+            let weights = &self.config.weights;
+            let plies = ENSEMBLE_DUEL_PLIES.min(max_depth);
+            let duel = search::minimax::search(state, enemy, weights, plies, true, |stats| {
+                self.out_of_budget(start_instant, budget, stats)
+            });
+            ballots.push(ballot(ENSEMBLE_DUEL_VOTE, duel));
+        }
+
+        let mut tally: Vec<(Action, f64)> = Vec::new();
+        for (vote, result) in ballots.iter() {
+            if let Some(&action) = result.plan.first() {
+                match tally.iter_mut().find(|(a, _)| *a == action) {
+                    Some((_, total)) => *total += vote,
+                    None => tally.push((action, *vote)),
+                }
+            }
+        }
+        let winner = tally
+            .iter()
+            .fold(
+                None,
+                |best: Option<(Action, f64)>, &(action, total)| match best {
+                    Some((_, best_total)) if best_total >= total => best,
+                    _ => Some((action, total)),
+                },
+            )
+            .map(|(action, _)| action);
+
+        let nodes: usize = ballots.iter().map(|(_, result)| result.stats.nodes).sum();
+        ballots.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        let mut result = ballots
+            .into_iter()
+            .map(|(_, result)| result)
+            .find(|result| result.plan.first().copied() == winner)
+            .expect("the winner comes from some ballot");
+        result.stats.nodes = nodes;
+        result
+    }
+
+    // logs when the static evaluation of the plan's leaf and a greedy rollout
+    // from it disagree enough that the static one is probably misleading
+    fn cross_check(&self, state: &State, plan: &[Action]) {
+        let weights = &self.config.weights;
+        let mut leaf = state.clone();
+        for &action in plan {
+            if leaf.try_apply(action).is_err() {
+                break;
+            }
+        }
+
+        let static_value = leaf.value_with_brew(weights);
+        let rollout_value = leaf.rollout_value(weights, CROSS_CHECK_ROLLOUT_DEPTH);
+        let disagreement = rollout_value - static_value;
+        if disagreement.abs() > CROSS_CHECK_THRESHOLD {
+            eprintln!(
+                "cross-check: leaf after {} actions, static {:.2} vs rollout {:.2} ({:+.2}), inventory {:?}",
+                plan.len(),
+                static_value,
+                rollout_value,
+                disagreement,
+                leaf.me.inventory
+            );
+        }
+    }
+
+    // false for the action classes Config switches off for ablations
+    fn may_play(&self, action: Action) -> bool {
+        match action {
+            Action::Learn(_) => MAX_TURNS - self.turns_left < self.config.no_learn_after,
+            _ => true,
+        }
+    }
+
+    // last turn's plan, if it still plays out on this turn's board and still
+    // ends where an order can be brewed; anything the opponent changed that
+    // breaks it (a taken order, new casts or tome ids) sends it back to search
+    fn still_valid_plan(&self, state: &State) -> Option<Vec<Action>> {
+        if self.plan_tail.is_empty() {
+            return None;
+        }
+        let mut end = state.clone();
+        for &action in self.plan_tail.iter() {
+            end.try_apply(action).ok()?;
+        }
+        end.find_brewable_order()?;
+        Some(self.plan_tail.clone())
+    }
+
+    // whether the opponent can brew a bonus order this turn or after one cast
+    fn is_threatened(&self, order: &Order) -> bool {
+        let enemy = match self.enemy.as_ref() {
+            Some(enemy) if order.bonus > 0 => enemy,
+            _ => return false,
+        };
+        order.can_be_fulfilled_by(enemy.inventory)
+            || enemy.spells.iter().any(|spell| {
+                spell.is_castable
+                    && spell.can_be_afforded_by(enemy.inventory)
+                    && order.can_be_fulfilled_by(enemy.inventory + spell.delta)
+            })
+    }
+
+    // a threatened order I can brew right now. There is no blocking learn to
+    // fall back on: the opponent's cast only uses spells they already own and
+    // a learn takes nothing from their inventory, so brewing first is the only
+    // way to deny them the order
+    fn sniping_alarm(&self, state: &State) -> Option<Order> {
+        state
+            .brewable_orders()
+            .filter(|order| self.is_threatened(order))
+            .max_by_key(|order| order.price)
+            .copied()
+    }
+
+    // threatened orders I can't brew this turn will likely be gone by the
+    // next, so the search stops counting on them
+    fn concede_sniped_orders(&self, state: &mut State) {
+        let mask = state.brewable_mask();
+        for (i, order) in state.orders.iter_mut().enumerate() {
+            if mask & 1 << i == 0 && self.is_threatened(order) {
+                order.claim = order.claim.min(SNIPED_CLAIM);
+            }
+        }
+    }
+
+    fn think(&self, start_instant: &Instant, state: &State) -> Decision {
+        if let Some(order) = self
+            .sniping_alarm(state)
+            .filter(|_| self.config.denial_brews)
+        {
+            eprintln!(
+                "alarm: opponent is one cast from order {} (+{} bonus), brewing it now",
+                order.id, order.bonus
+            );
+            return Decision::immediate(Action::Brew(order.id));
+        }
+
+        if let Some(order) = state.find_brewable_order() {
+            return Decision::immediate(Action::Brew(order.id));
+        }
+
+        // every order left is out of reach: searching for brews is pointless,
+        // the end bonus for higher-tier ingredients is all that is left
+        if state.orders.is_empty() {
+            let action = state
+                .find_liquidation_action(self.turns_left)
+                .unwrap_or(Action::Wait);
+            return Decision::immediate(action);
+        }
+
+        if state.is_inventory_stuck() {
+            if let Some(action) = state
+                .find_unjam_action(&self.config.weights)
+                .filter(|&a| self.may_play(a))
+            {
+                return Decision::immediate(action);
+            }
+        }
+
+        if self.config.reuse_plans {
+            if let Some(plan) = self.still_valid_plan(state) {
+                let mut decision = Decision::immediate(plan[0]);
+                decision.plan = plan;
+                return decision;
+            }
+        }
+
+        // fallback ladder: each stage only runs if the one before it ran out of
+        // its share of the budget without producing a plan
+        let full_budget = self.search_budget(FULL_SEARCH_SHARE);
+        let result = self.search(start_instant, state, full_budget, usize::MAX);
+
+        if self.config.explain {
+            eprintln!(
+                "{} nodes, depth {}, {} commuting casts pruned, {} dominated, {} cutoffs",
+                result.stats.nodes,
+                result.stats.max_depth,
+                result.stats.pruned,
+                result.stats.dominated,
+                result.stats.cutoffs
+            );
+            let stats = result.stats;
+            eprintln!(
+                "memory: visited {}, arena {}, frontier {}, ~{} KiB",
+                stats.peak_visited,
+                stats.peak_arena,
+                stats.peak_frontier,
+                stats.estimated_bytes(state.approx_bytes()) / 1024
+            );
+            result.root_values.explain();
+            for (rank, alternative) in result.alternatives.iter().enumerate() {
+                eprintln!("  #{} {}", rank + 1, alternative);
+            }
+            self.explain_leaf(state, &result.plan);
+        }
+
+        if self.config.cross_check {
+            self.cross_check(state, &result.plan);
+        }
+
+        if !result.plan.is_empty() {
+            return Decision::from_search(result);
+        }
+
+        eprintln!("full search produced no plan, trying a shallow one");
+        let shallow_budget = self.search_budget(SHALLOW_SEARCH_SHARE);
+        let shallow = self.search(start_instant, state, shallow_budget, SHALLOW_SEARCH_DEPTH);
+        if !shallow.plan.is_empty() {
+            return Decision::from_search(shallow);
+        }
+
+        eprintln!("shallow search produced no plan, acting greedily");
+        if let Some(action) = state.find_greedy_action(&self.config.weights) {
+            return Decision::new(action, result.stats);
+        }
+
+        let action = if state.get_possible_actions().contains(&Action::Rest) {
+            Action::Rest
+        } else {
+            Action::Wait
+        };
+        Decision::new(action, result.stats)
+    }
+}
+
+// Orders proven unbrewable with the current spell set. Without a spell
+// producing a tier, the inventory can only lose that tier, and the turns
+// left only shrink, so a proof holds until the spell set changes.
+#[derive(Debug, Default)]
+struct UnreachableOrders {
+    spell_ids: Vec<EngineId>,
+    order_ids: HashSet<EngineId>,
+}
+
+impl UnreachableOrders {
+    fn refresh(&mut self, state: &State, turns_left: u32) {
+        let spell_ids: Vec<EngineId> = state.me.spells.iter().map(|s| s.id).collect();
+        if spell_ids != self.spell_ids {
+            self.spell_ids = spell_ids;
+            self.order_ids.clear();
+        }
+
+        for order in state.orders.iter() {
+            if !self.order_ids.contains(&order.id) && !state.is_order_reachable(*order, turns_left)
+            {
+                eprintln!(
+                    "order {} cannot be brewed with the current spells",
+                    order.id
+                );
+                self.order_ids.insert(order.id);
+            }
+        }
+    }
+
+    // the state the search should see: no paths toward proven dead ends
+    fn without_unreachable(&self, state: &State) -> State {
+        let mut search_state = state.clone();
+        search_state
+            .orders
+            .retain(|o| !self.order_ids.contains(&o.id));
+        search_state
+    }
+}
+
+// {"turn":0,"time_ms":0.5,"action":"CAST 78","plan":["CAST 78",...],"roots":[{"action":"CAST 78","value":12.5},...]}
+fn timeline_json(turn: usize, elapsed: Duration, decision: &Decision) -> String {
+    let plan: Vec<String> = decision
+        .plan
+        .iter()
+        .map(|a| format!("\"{}\"", a.to_command()))
+        .collect();
+    let roots: Vec<String> = decision
+        .root_values
+        .iter()
+        .map(|(a, value)| {
+            format!(
+                "{{\"action\":\"{}\",\"value\":{:.3}}}",
+                a.to_command(),
+                value
+            )
+        })
+        .collect();
+    let alternatives: Vec<String> = decision
+        .alternatives
+        .iter()
+        .map(|alternative| {
+            let plan: Vec<String> = alternative
+                .plan
+                .iter()
+                .map(|a| format!("\"{}\"", a.to_command()))
+                .collect();
+            format!(
+                "{{\"plan\":[{}],\"value\":{:.3}}}",
+                plan.join(","),
+                alternative.value
+            )
+        })
+        .collect();
+    format!(
+        "{{\"turn\":{},\"time_ms\":{:.3},\"action\":\"{}\",\"plan\":[{}],\"roots\":[{}],\"alternatives\":[{}]}}",
+        turn,
+        elapsed.as_secs_f64() * 1000.0,
+        decision.action.to_command(),
+        plan.join(","),
+        roots.join(","),
+        alternatives.join(",")
+    )
+}
+
+// Match totals for the end-of-game summary line.
+#[derive(Debug, Default)]
+struct GameStats {
+    brews: u32,
+    learns: u32,
+    rests: u32,
+    rupees: i32,
+    turn_times: Vec<Duration>,
+    max_depth: usize,
+    // largest SearchStats::estimated_bytes of any turn
+    peak_bytes: usize,
+    flow: IngredientFlow,
+    forecasts: PlanForecasts,
+}
+
+const PHASE_NAMES: [&str; 3] = ["opening", "middle", "endgame"];
+
+// game phase by potions brewed so far, two per phase
+fn phase_of(brews: u32) -> usize {
+    (brews as usize / 2).min(PHASE_NAMES.len() - 1)
+}
+
+// The rupees each adopted plan predicted against what it earned by the time
+// it completed or was dropped, per game phase. A positive bias means the
+// searches keep promising brews that don't happen.
+#[derive(Debug, Default)]
+struct PlanForecasts {
+    active: Option<Forecast>,
+    closed: [u32; 3],
+    // sum of predicted minus realized rupees
+    error: [i32; 3],
+}
+
+#[derive(Debug)]
+struct Forecast {
+    // the plan's actions not played yet
+    remaining: Vec<Action>,
+    start_score: i32,
+    predicted: i32,
+    phase: usize,
+}
+
+impl PlanForecasts {
+    // `decision` was just taken from `state`: either the active plan's next
+    // step, or the start of a new plan that replaces it
+    fn record(&mut self, state: &State, decision: &Decision, phase: usize) {
+        if let Some(active) = self.active.as_mut() {
+            if active.remaining.first() == Some(&decision.action) {
+                active.remaining.remove(0);
+                return;
+            }
+        }
+        self.close(state.me.score);
+
+        let mut end = state.clone();
+        let mut remaining: Vec<Action> = decision.plan.iter().skip(1).copied().collect();
+        for &action in decision.plan.iter() {
+            if end.try_apply(action).is_err() {
+                break;
+            }
+        }
+        // plans stop where an order becomes brewable, and Bot::think brews
+        // it the turn after: count that brew as part of the plan
+        if let Some(order) = end.find_brewable_order() {
+            end.apply_unchecked(Action::Brew(order.id));
+            remaining.push(Action::Brew(order.id));
+        }
+        self.active = Some(Forecast {
+            remaining,
+            start_score: state.me.score,
+            predicted: end.me.score - state.me.score,
+            phase,
+        });
+    }
+
+    fn close(&mut self, score: i32) {
+        if let Some(forecast) = self.active.take() {
+            self.closed[forecast.phase] += 1;
+            self.error[forecast.phase] += forecast.predicted - (score - forecast.start_score);
+        }
+    }
+
+    // counts the plan still running as ending at `final_score`
+    fn report_line(&self, final_score: i32) -> String {
+        let mut closed = self.closed;
+        let mut error = self.error;
+        if let Some(forecast) = self.active.as_ref() {
+            closed[forecast.phase] += 1;
+            error[forecast.phase] += forecast.predicted - (final_score - forecast.start_score);
+        }
+        let phases: Vec<String> = PHASE_NAMES
+            .iter()
+            .enumerate()
+            .map(|(phase, name)| {
+                let bias = error[phase] as f64 / closed[phase].max(1) as f64;
+                format!("{}: plans={} bias={:+.2}", name, closed[phase], bias)
+            })
+            .collect();
+        format!("FORECAST {}", phases.join(", "))
+    }
+}
+
+// Where each tier's ingredients went over a game, to spot systematic waste
+// the evaluation should be penalizing: a high `lost` means learns wasted
+// their tier-0 bonus on a full inventory, a high `left` means ingredients
+// were produced that no order ever took.
+#[derive(Debug, Default)]
+struct IngredientFlow {
+    // by casts and learn bonuses
+    produced: [i32; 4],
+    // by casts and learn taxes
+    spent: [i32; 4],
+    brewed: [i32; 4],
+    // learn bonus that didn't fit under MAX_INVENTORY
+    lost: [i32; 4],
+    // the last inventory seen
+    left: [i32; 4],
+}
+
+impl IngredientFlow {
+    // counts `action` as played from `state`; illegal actions move nothing
+    fn record(&mut self, state: &State, action: Action) {
+        if !state.is_legal(action) {
+            return;
+        }
+        match action {
+            Action::Cast(id) => {
+                let slot = state.spell_slot(id).unwrap();
+                let delta = state.me.spells[slot.0].delta.to_array();
+                for (tier, &count) in delta.iter().enumerate() {
+                    self.produced[tier] += count.max(0);
+                    self.spent[tier] += (-count).max(0);
+                }
+            }
+            Action::Brew(id) => {
+                let slot = state.order_slot(id).unwrap();
+                let delta = state.orders[slot.0].delta.to_array();
+                for (tier, &count) in delta.iter().enumerate() {
+                    self.brewed[tier] -= count;
+                }
+            }
+            Action::Learn(id) => {
+                let slot = state.tome_slot(id).unwrap();
+                let spell = state.tome.spells[slot.0];
+                let space = MAX_INVENTORY - state.me.inventory.total() + spell.tome_index;
+                let kept = spell.tax_count.min(space);
+                self.spent[0] += spell.tome_index;
+                self.produced[0] += kept;
+                self.lost[0] += spell.tax_count - kept;
+            }
+            Action::Rest | Action::Wait => {}
+        }
+    }
+
+    fn report_line(&self) -> String {
+        format!(
+            "FLOW produced={:?} spent={:?} brewed={:?} lost={:?} left={:?}",
+            self.produced, self.spent, self.brewed, self.lost, self.left
+        )
+    }
+}
+
+impl GameStats {
+    fn record_turn(&mut self, decision: &Decision, elapsed: Duration, state_bytes: usize) {
+        match decision.action {
+            Action::Brew(_) => self.brews += 1,
+            Action::Learn(_) => self.learns += 1,
+            Action::Rest => self.rests += 1,
+            _ => {}
+        }
+        self.turn_times.push(elapsed);
+        self.max_depth = self.max_depth.max(decision.stats.max_depth);
+        let bytes = decision.stats.estimated_bytes(state_bytes);
+        self.peak_bytes = self.peak_bytes.max(bytes);
+    }
+
+    fn average_turn_time(&self) -> Duration {
+        if self.turn_times.is_empty() {
+            return Duration::new(0, 0);
+        }
+        self.turn_times.iter().sum::<Duration>() / self.turn_times.len() as u32
+    }
+
+    fn turn_time_percentile(&self, percentile: usize) -> Duration {
+        let mut times = self.turn_times.clone();
+        times.sort();
+        match times.len() {
+            0 => Duration::new(0, 0),
+            len => times[(len - 1) * percentile / 100],
+        }
+    }
+
+    fn summary_line(&self) -> String {
+        format!(
+            "SUMMARY turns={} brews={} rupees={} learns={} rests={} avg_ms={:.3} p95_ms={:.3} max_depth={} peak_kib={}",
+            self.turn_times.len(),
+            self.brews,
+            self.rupees,
+            self.learns,
+            self.rests,
+            self.average_turn_time().as_secs_f64() * 1000.0,
+            self.turn_time_percentile(95).as_secs_f64() * 1000.0,
+            self.max_depth,
+            self.peak_bytes / 1024,
+        )
+    }
+}
+
+// games end after 100 turns or once a player has brewed six potions
+const MAX_TURNS: u32 = 100;
+const MAX_BREWS: u32 = 6;
+
+// Drives a bot from judge-formatted turn input without touching stdin or stdout,
+// so it can be embedded by anything that can produce the turn text.
+struct Session {
+    bot: Bot,
+    state: Option<State>,
+    enemy: Option<Player>,
+    stats: GameStats,
+    // turn on which each order id first appeared
+    order_first_seen: HashMap<EngineId, u32>,
+    unreachable: UnreachableOrders,
+    // one JSON line per decision, for the turn-by-turn visualizer
+    timeline: Option<fs::File>,
+    // what the last decide chose, for advance to play in local mode
+    last_action: Option<Action>,
+}
+
+impl Session {
+    fn new(config: Config) -> Self {
+        Self {
+            bot: Bot::new(config),
+            state: None,
+            enemy: None,
+            stats: GameStats::default(),
+            order_first_seen: HashMap::new(),
+            unreachable: UnreachableOrders::default(),
+            timeline: None,
+            last_action: None,
+        }
+    }
+
+    fn turn(&self) -> u32 {
+        self.stats.turn_times.len() as u32
+    }
+
+    fn observe(&mut self, turn_input: &str) {
+        let (mut state, enemy) = State::parse_with_enemy(turn_input);
+        state.me.brew_count = self.stats.brews;
+        self.stats.rupees = state.me.score;
+        self.stats.flow.left = state.me.inventory.to_array();
+        self.state = Some(state);
+        self.enemy = Some(enemy);
+        self.refresh_order_ages();
+    }
+
+    fn refresh_order_ages(&mut self) {
+        let turn = self.turn();
+        let state = self.state.as_mut().unwrap();
+        for order in state.orders.iter_mut() {
+            let first_seen = *self.order_first_seen.entry(order.id).or_insert(turn);
+            order.age = turn - first_seen;
+        }
+    }
+
+    // local mode: play the last chosen action on our own copy of the state
+    // instead of waiting for the judge to send the next turn
+    fn advance(&mut self) {
+        let action = self.last_action.take().expect("advance called before act");
+        let state = self.state.as_mut().expect("advance called before observe");
+        if let Err(error) = state.try_apply(action) {
+            eprintln!("{}, turn wasted", error);
+        }
+        self.stats.rupees = state.me.score;
+        self.stats.flow.left = state.me.inventory.to_array();
+        self.refresh_order_ages();
+    }
+
+    fn is_game_over(&self) -> bool {
+        let state = self
+            .state
+            .as_ref()
+            .expect("is_game_over called before observe");
+        self.turn() >= MAX_TURNS || self.stats.brews >= MAX_BREWS || state.orders.is_empty()
+    }
+
+    fn decide(&mut self) -> Action {
+        let start_instant = Instant::now();
+        let turns_left = MAX_TURNS.saturating_sub(self.turn());
+        let state = self.state.as_ref().expect("decide called before observe");
+        if self.turn() == 0 {
+            self.bot.calibrate(state);
+        }
+
+        self.bot
+            .set_risk(state.score_margin(), turns_left, state.engine_gap());
+        self.bot.set_race_share(state.score_margin(), turns_left);
+        self.unreachable.refresh(state, turns_left);
+        let mut search_state = self.unreachable.without_unreachable(state);
+        self.bot.turns_left = turns_left;
+        self.bot.first_turn = self.turn() == 0;
+        self.bot.spent.set(0);
+        if let Some(enemy) = self.enemy.as_ref() {
+            self.bot
+                .estimate_races(&start_instant, &mut search_state, enemy);
+        }
+        self.bot.enemy = self.enemy.clone();
+        if self.bot.config.denial_brews {
+            self.bot.concede_sniped_orders(&mut search_state);
+        }
+
+        let mut decision = self.bot.think(&start_instant, &search_state);
+        // the search ran on an adjusted copy; an action illegal on the board
+        // the judge actually sent would lose the turn
+        if !state.is_legal(decision.action) {
+            let fallback = state
+                .find_greedy_action(&self.bot.config.weights)
+                .unwrap_or(Action::Wait);
+            eprintln!(
+                "guard: {} is illegal on the parsed state, playing {} instead",
+                decision.action, fallback
+            );
+            decision = Decision::new(fallback, decision.stats);
+        }
+        self.bot.plan_tail = match decision.plan.split_first() {
+            Some((&first, rest)) if first == decision.action => rest.to_vec(),
+            _ => Vec::new(),
+        };
+        if self.bot.config.history {
+            // next turn's depth 0 is this plan's second action
+            self.bot.history.age();
+            self.bot.history.credit(&self.bot.plan_tail);
+        }
+        self.bot.warm_plans = decision
+            .survivors
+            .iter()
+            .filter_map(|plan| match plan.split_first() {
+                Some((&first, rest)) if first == decision.action && !rest.is_empty() => {
+                    Some(rest.to_vec())
+                }
+                _ => None,
+            })
+            .collect();
+        let elapsed = start_instant.elapsed();
+        self.stats
+            .record_turn(&decision, elapsed, search_state.approx_bytes());
+        self.stats.flow.record(state, decision.action);
+        let phase = phase_of(self.stats.brews);
+        self.stats.forecasts.record(state, &decision, phase);
+
+        if let Some(timeline) = self.timeline.as_mut() {
+            let line = timeline_json(self.stats.turn_times.len() - 1, elapsed, &decision);
+            if let Err(error) = writeln!(timeline, "{}", line) {
+                eprintln!("timeline: {}", error);
+            }
+        }
+
+        self.last_action = Some(decision.action);
+        decision.action
+    }
+
+    // the command for the judge this turn
+    fn act(&mut self) -> String {
+        self.decide().to_command()
+    }
+
+    fn stats(&self) -> &GameStats {
+        &self.stats
+    }
+
+    fn set_timeline(&mut self, file: fs::File) {
+        self.timeline = Some(file);
+    }
+
+    fn config_mut(&mut self) -> &mut Config {
+        &mut self.bot.config
+    }
+}
+
+// Where commands end up: the judge's stdout, or a recording for dry runs.
+trait Emitter {
+    fn emit(&mut self, command: &str);
+}
+
+struct StdoutEmitter;
+
+impl Emitter for StdoutEmitter {
+    fn emit(&mut self, command: &str) {
+        println!("{}", command);
+    }
+}
+
+#[derive(Default)]
+struct DryRunEmitter {
+    commands: Vec<String>,
+}
+
+impl DryRunEmitter {
+    fn commands(&self) -> &[String] {
+        &self.commands
+    }
+}
+
+impl Emitter for DryRunEmitter {
+    fn emit(&mut self, command: &str) {
+        self.commands.push(command.to_string());
+    }
+}
+
+// A parameter file re-read between turns whenever it changes on disk.
+struct ControlFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl ControlFile {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            modified: None,
+        }
+    }
+
+    // the file's contents if it changed since the last poll
+    fn poll(&mut self) -> Option<String> {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok()?;
+        if self.modified == Some(modified) {
+            return None;
+        }
+        self.modified = Some(modified);
+        fs::read_to_string(&self.path).ok()
+    }
+}
+
+// Where each turn comes from: the judge, or a game the bot plays against
+// its own copy of the board.
+trait InputSource {
+    // brings `session` to the next turn; false once there is none
+    fn next_turn(&mut self, session: &mut Session) -> bool;
+}
+
+// turns as the judge sends them, each read once the previous one is answered
+struct JudgeInput<R> {
+    reader: R,
+}
+
+impl<R: BufRead> InputSource for JudgeInput<R> {
+    fn next_turn(&mut self, session: &mut Session) -> bool {
+        match read_turn_input(&mut self.reader) {
+            Some(turn_input) => {
+                session.observe(&turn_input);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// one solo game played forward from a single turn
+struct LocalGame {
+    first_turn: Option<String>,
+}
+
+impl InputSource for LocalGame {
+    fn next_turn(&mut self, session: &mut Session) -> bool {
+        match self.first_turn.take() {
+            Some(turn_input) => session.observe(&turn_input),
+            None => session.advance(),
+        }
+        !session.is_game_over()
+    }
+}
+
+// the same turn MAX_TURNS times over, for timing the search on one board
+struct Replay {
+    turn_input: String,
+    turns_left: u32,
+}
+
+impl InputSource for Replay {
+    fn next_turn(&mut self, session: &mut Session) -> bool {
+        if self.turns_left == 0 {
+            return false;
+        }
+        self.turns_left -= 1;
+        session.observe(&self.turn_input);
+        true
+    }
+}
+
+struct RunOptions {
+    control: Option<ControlFile>,
+}
+
+impl RunOptions {
+    fn from_args(args: &[String]) -> Self {
+        let control = args
+            .iter()
+            .position(|arg| arg == "--control")
+            .and_then(|i| args.get(i + 1))
+            .map(|path| ControlFile::new(PathBuf::from(path)));
+        Self { control }
+    }
+}
+
+// Plays every turn `source` has, then prints the summary line.
+fn run_turns(
+    session: &mut Session,
+    source: &mut dyn InputSource,
+    options: &mut RunOptions,
+    emitter: &mut impl Emitter,
+) {
+    let mut turn = 0;
+    while source.next_turn(session) {
+        if let Some(overrides) = options.control.as_mut().and_then(|c| c.poll()) {
+            eprintln!("turn {}: reloading control file", turn);
+            session.config_mut().apply_overrides(&overrides);
+        }
+
+        let command = session.act();
+        emitter.emit(&command);
+        turn += 1;
+    }
+
+    eprintln!("{}", session.stats().summary_line());
+    eprintln!("{}", session.stats().flow.report_line());
+    let stats = session.stats();
+    eprintln!("{}", stats.forecasts.report_line(stats.rupees));
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut options = RunOptions::from_args(&args);
+
+    let mut session = Session::new(Config::from_args(&args));
+    if let Some(path) = args
+        .iter()
+        .position(|arg| arg == "--timeline")
+        .and_then(|i| args.get(i + 1))
+    {
+        match fs::File::create(path) {
+            Ok(file) => session.set_timeline(file),
+            Err(error) => eprintln!("timeline: {}: {}", path, error),
+        }
+    }
+
+    let stdin = io::stdin();
+    let mut reader = stdin.lock();
+    let has_flag = |flag: &str| args.iter().any(|arg| arg == flag);
+    let mut first_turn = || read_turn_input(&mut reader).expect("no turn input on stdin");
+
+    if has_flag("--tome-report") {
+        print_tome_report(&State::parse(&first_turn()));
+        return;
+    }
+
+    let mut source: Box<dyn InputSource> = if has_flag("--local") {
+        Box::new(LocalGame {
+            first_turn: Some(first_turn()),
+        })
+    } else if has_flag("--replay") {
+        Box::new(Replay {
+            turn_input: first_turn(),
+            turns_left: MAX_TURNS,
+        })
+    } else {
+        Box::new(JudgeInput { reader })
+    };
+
+    if has_flag("--dry-run") {
+        let mut emitter = DryRunEmitter::default();
+        run_turns(&mut session, source.as_mut(), &mut options, &mut emitter);
+        for (turn, command) in emitter.commands().iter().enumerate() {
+            eprintln!("turn {}: {}", turn, command);
+        }
+    } else {
+        run_turns(
+            &mut session,
+            source.as_mut(),
+            &mut options,
+            &mut StdoutEmitter,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,205 @@
+// Plays each fixture out through the real binary in --local mode with the
+// default config, the one that ships, and checks what the judge would see:
+// one well-formed command per turn, none the bot itself rejects, and turns
+// that fit the judge's time limit. A recorded 60-turn game is also piped
+// through the judge protocol itself, one turn read per command written.
+
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+const FIXTURES: [&str; 3] = ["input.txt", "input2.txt", "input3.txt"];
+// judge input for every turn of a self-play game, see record_self_play_game
+const RECORDED_GAME: &str = "tests/recorded_game.txt";
+// the judge allows 50ms per turn after the first
+const TURN_BUDGET_MS: f64 = 50.0;
+
+struct Game {
+    commands: Vec<String>,
+    stderr: String,
+}
+
+fn read_fixture(fixture: &str) -> String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(fixture);
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", fixture, e))
+}
+
+fn play(fixture: &str, args: &[&str]) -> Game {
+    let input = read_fixture(fixture);
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_fall-challenge-2020-rust"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("could not start the bot");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}: bot exited with an error",
+        fixture
+    );
+
+    Game {
+        commands: String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    }
+}
+
+fn is_well_formed(command: &str) -> bool {
+    let mut parts = command.split(' ');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some("REST"), None, _) | (Some("WAIT"), None, _) => true,
+        (Some("CAST"), Some(id), None)
+        | (Some("BREW"), Some(id), None)
+        | (Some("LEARN"), Some(id), None) => id.parse::<i32>().is_ok(),
+        _ => false,
+    }
+}
+
+// one turn's entity lines and my inventory line, as the judge sent them
+struct Turn<'a> {
+    entities: Vec<Vec<&'a str>>,
+    inventory: [i32; 4],
+}
+
+fn split_turns(stream: &str) -> Vec<Turn<'_>> {
+    let mut lines = stream.lines().filter(|line| !line.trim().is_empty());
+    let mut turns = Vec::new();
+    while let Some(count) = lines.next() {
+        let count: usize = count.trim().parse().unwrap();
+        let entities = (0..count)
+            .map(|_| lines.next().unwrap().split(' ').collect())
+            .collect();
+        let mut inventory = [0; 4];
+        for (tier, count) in lines.next().unwrap().split(' ').take(4).enumerate() {
+            inventory[tier] = count.parse().unwrap();
+        }
+        lines.next().unwrap();
+        turns.push(Turn {
+            entities,
+            inventory,
+        });
+    }
+    turns
+}
+
+// what the judge accepts: the id names an entity of the right kind, and its
+// cost fits my inventory
+fn is_legal(turn: &Turn, command: &str) -> bool {
+    let mut parts = command.split(' ');
+    let (kind, id) = match (parts.next(), parts.next()) {
+        (Some("REST"), None) | (Some("WAIT"), None) => return true,
+        (Some("BREW"), Some(id)) => ("BREW", id),
+        (Some("CAST"), Some(id)) => ("CAST", id),
+        (Some("LEARN"), Some(id)) => ("LEARN", id),
+        _ => return false,
+    };
+    let entity = match turn.entities.iter().find(|e| e[0] == id && e[1] == kind) {
+        Some(entity) => entity,
+        None => return false,
+    };
+    let field = |index: usize| entity[index].parse::<i32>().unwrap();
+    let after: Vec<i32> = (0..4)
+        .map(|tier| turn.inventory[tier] + field(2 + tier))
+        .collect();
+    match kind {
+        "BREW" => after.iter().all(|&count| count >= 0),
+        "CAST" => {
+            field(9) == 1
+                && after.iter().all(|&count| count >= 0)
+                && after.iter().sum::<i32>() <= 10
+        }
+        _ => field(7) <= turn.inventory[0],
+    }
+}
+
+fn summary_field(summary: &str, name: &str) -> f64 {
+    let prefix = format!("{}=", name);
+    summary
+        .split(' ')
+        .find_map(|field| field.strip_prefix(prefix.as_str()))
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| panic!("no {} in '{}'", name, summary))
+}
+
+#[test]
+fn local_games_are_legal_and_within_the_time_limit() {
+    for fixture in FIXTURES.iter() {
+        let game = play(fixture, &["--local"]);
+        assert!(!game.commands.is_empty(), "{}: no commands", fixture);
+        for (turn, command) in game.commands.iter().enumerate() {
+            assert!(
+                is_well_formed(command),
+                "{} turn {}: malformed command '{}'",
+                fixture,
+                turn,
+                command
+            );
+        }
+        if let Some(line) = game.stderr.lines().find(|l| l.contains("turn wasted")) {
+            panic!("{}: illegal command played: {}", fixture, line);
+        }
+
+        let summary = game
+            .stderr
+            .lines()
+            .find(|l| l.starts_with("SUMMARY"))
+            .unwrap_or_else(|| panic!("{}: no SUMMARY line", fixture));
+        assert_eq!(
+            summary_field(summary, "turns") as usize,
+            game.commands.len(),
+            "{}: one command per turn",
+            fixture
+        );
+        assert!(
+            summary_field(summary, "p95_ms") <= TURN_BUDGET_MS,
+            "{}: turns over the judge's limit: {}",
+            fixture,
+            summary
+        );
+    }
+}
+
+#[test]
+fn recorded_game_gets_one_legal_command_per_turn() {
+    let stream = read_fixture(RECORDED_GAME);
+    let turns = split_turns(&stream);
+    let game = play(RECORDED_GAME, &[]);
+    assert_eq!(
+        game.commands.len(),
+        turns.len(),
+        "one command per turn read"
+    );
+    for (number, (turn, command)) in turns.iter().zip(game.commands.iter()).enumerate() {
+        assert!(
+            is_legal(turn, command),
+            "turn {}: '{}' is illegal on the board the judge sent",
+            number,
+            command
+        );
+    }
+
+    let summary = game
+        .stderr
+        .lines()
+        .find(|l| l.starts_with("SUMMARY"))
+        .expect("no SUMMARY line");
+    assert!(
+        summary_field(summary, "p95_ms") <= TURN_BUDGET_MS,
+        "turns over the judge's limit: {}",
+        summary
+    );
+}